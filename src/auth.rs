@@ -0,0 +1,216 @@
+use anyhow::{Context, Result, bail};
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Caller identified by its bearer token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Client {
+    /// Shown in the logs
+    pub name: String,
+    /// Collections the client may read, `None` for all of them
+    pub collections: Option<Vec<String>>,
+}
+
+impl Client {
+    /// Whether the client may read the collection
+    pub fn allows(&self, collection: &str) -> bool {
+        self.collections
+            .as_ref()
+            .is_none_or(|c| c.iter().any(|name| name == collection))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+    #[serde(default)]
+    clients: Vec<ClientConfig>,
+}
+
+/// One `[[clients]]` entry of the token file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientConfig {
+    name: String,
+    token: String,
+    collections: Option<Vec<String>>,
+}
+
+/// Bearer tokens accepted by the HTTP transports, auth is off when there are none
+#[derive(Debug, Default, Clone)]
+pub struct Auth {
+    clients: HashMap<String, Arc<Client>>,
+}
+
+impl Auth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, token: &str, client: Client) -> Result<()> {
+        if token.is_empty() {
+            bail!("client `{}` has an empty token", client.name);
+        }
+        if let Some(other) = self.clients.insert(token.to_string(), Arc::new(client)) {
+            bail!(
+                "client `{}` shares its token with another client",
+                other.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Adds comma separated tokens, `name:token` names the client, a bare token
+    /// is logged as `token-{n}`. These clients may read every collection
+    pub fn with_tokens(mut self, list: &str) -> Result<Self> {
+        for (i, entry) in list
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .enumerate()
+        {
+            let (name, token) = match entry.split_once(':') {
+                Some((name, token)) => (name.to_string(), token),
+                None => (format!("token-{}", i + 1), entry),
+            };
+            self.add(
+                token,
+                Client {
+                    name,
+                    collections: None,
+                },
+            )?;
+        }
+        Ok(self)
+    }
+
+    /// Adds the `[[clients]]` of a TOML file, each with a name, a token and
+    /// optionally the collections it may read
+    pub fn with_file(mut self, path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read token file: {}", path.display()))?;
+        let file: TokenFile = toml::from_str(&content)
+            .with_context(|| format!("Invalid token file: {}", path.display()))?;
+        for client in file.clients {
+            self.add(
+                &client.token,
+                Client {
+                    name: client.name,
+                    collections: client.collections,
+                },
+            )?;
+        }
+        Ok(self)
+    }
+
+    /// Whether any token is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Client of the `Authorization: Bearer` header
+    pub fn authenticate(&self, headers: &HeaderMap) -> Option<Arc<Client>> {
+        let token = headers
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?
+            .trim();
+        self.clients.get(token).cloned()
+    }
+}
+
+/// Middleware answering 401 to requests without a known token, the client
+/// is stored in the request extensions for the transports
+pub async fn require_bearer(
+    State(auth): State<Arc<Auth>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match auth.authenticate(request.headers()) {
+        Some(client) => {
+            log::info!(
+                "{} {} by client {}",
+                request.method(),
+                request.uri().path(),
+                client.name
+            );
+            request.extensions_mut().insert(client);
+            next.run(request).await
+        }
+        None => {
+            log::warn!(
+                "rejected {} {}: missing or unknown bearer token",
+                request.method(),
+                request.uri().path()
+            );
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                "missing or unknown bearer token",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let path = std::env::temp_dir().join(format!("test_auth_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [[clients]]
+            name = "ci"
+            token = "ci-secret"
+            collections = ["laravel_docs"]
+        "#,
+        )
+        .unwrap();
+        let auth = Auth::new()
+            .with_tokens("alice:a-secret, b-secret")
+            .unwrap()
+            .with_file(&path)
+            .unwrap();
+        assert!(auth.is_enabled());
+
+        let alice = auth.authenticate(&bearer("a-secret")).unwrap();
+        assert_eq!(alice.name, "alice");
+        assert!(alice.allows("internal_docs"));
+        assert_eq!(
+            auth.authenticate(&bearer("b-secret")).unwrap().name,
+            "token-2"
+        );
+        let ci = auth.authenticate(&bearer("ci-secret")).unwrap();
+        assert!(ci.allows("laravel_docs"));
+        assert!(!ci.allows("internal_docs"));
+
+        assert!(auth.authenticate(&bearer("wrong")).is_none());
+        assert!(auth.authenticate(&HeaderMap::new()).is_none());
+        assert!(Auth::new().with_tokens("a:x,b:x").is_err());
+        assert!(!Auth::new().with_tokens(" ").unwrap().is_enabled());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
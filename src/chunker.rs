@@ -1,12 +1,83 @@
+use crate::markdown_splitter::{Heading, MarkdownTextSplitter, headings};
 use crate::text_splitter::RecursiveCharacterTextSplitter;
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use walkdir::WalkDir;
 
+/// Which splitter is used to cut files into chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitMode {
+    /// Generic separators (`\n\n`, `. `, `, ` ...)
+    #[default]
+    Recursive,
+    /// Markdown block boundaries, code fences and tables are never cut
+    Markdown,
+}
+
+impl FromStr for SplitMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "recursive" => Ok(Self::Recursive),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(anyhow!(
+                "unknown splitter `{}`, expected `recursive` or `markdown`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SplitMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Recursive => write!(f, "recursive"),
+            Self::Markdown => write!(f, "markdown"),
+        }
+    }
+}
+
+/// Splitter settings the chunks of a docs version were cut with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkSettings {
+    pub mode: SplitMode,
+    /// Maximum size of chunks in characters
+    pub chunk_size: usize,
+    /// Overlap between chunks in characters
+    pub chunk_overlap: usize,
+}
+
+impl fmt::Display for ChunkSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} size {} / overlap {}",
+            self.mode, self.chunk_size, self.chunk_overlap
+        )
+    }
+}
+
+enum Splitter {
+    Recursive(RecursiveCharacterTextSplitter),
+    Markdown(MarkdownTextSplitter),
+}
+
+impl Splitter {
+    fn split_text(&self, text: &str) -> Vec<String> {
+        match self {
+            Splitter::Recursive(s) => s.split_text(text),
+            Splitter::Markdown(s) => s.split_text(text),
+        }
+    }
+}
+
 /// Represents a single text chunk with metadata
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TextChunk {
@@ -16,6 +87,87 @@ pub struct TextChunk {
     pub text: String,
     /// Source file path where this chunk originated
     pub source: String,
+    /// Heading path the chunk sits under, e.g. `Eloquent: Relationships > Many To Many`
+    #[serde(default)]
+    pub section: String,
+    /// Anchor slug of the nearest heading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<String>,
+    /// Docs version the chunk was ingested as, e.g. `11.x`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Heading breadcrumb and nearest anchor at a byte offset of the document
+fn section_at(headings: &[Heading], offset: usize) -> (String, Option<String>) {
+    let mut path: Vec<&Heading> = Vec::new();
+    for heading in headings.iter().take_while(|h| h.offset <= offset) {
+        while path.last().is_some_and(|h| h.level >= heading.level) {
+            path.pop();
+        }
+        path.push(heading);
+    }
+
+    let section = path
+        .iter()
+        .map(|h| h.title.as_str())
+        .collect::<Vec<_>>()
+        .join(" > ");
+    (section, path.last().map(|h| h.anchor.clone()))
+}
+
+/// Ids of the chunks around a chunk id `{uid}-{index}`, from `before` chunks
+/// before it to `after` chunks after it, in file order
+pub fn neighbour_ids(id: &str, before: usize, after: usize) -> Option<Vec<String>> {
+    let (uid, index) = id.rsplit_once('-')?;
+    let index: usize = index.parse().ok()?;
+    Some(
+        (index.saturating_sub(before)..=index.saturating_add(after))
+            .map(|i| format!("{}-{}", uid, i))
+            .collect(),
+    )
+}
+
+/// Chunk overlap used when none is given, also assumed for docs versions
+/// ingested before their splitter settings were recorded
+pub const DEFAULT_CHUNK_OVERLAP: usize = 20;
+
+/// Joins consecutive chunks of one file back into text, dropping the
+/// `overlap` characters the splitter repeated from the end of the previous
+/// chunk. Chunks that do not start with that repeat, e.g. ones cut at block
+/// boundaries, are joined with a blank line
+pub fn stitch_chunks(chunks: &[&str], overlap: usize) -> String {
+    let mut text = String::new();
+    let mut previous = "";
+    for chunk in chunks {
+        if text.is_empty() {
+            text.push_str(chunk);
+            previous = chunk;
+            continue;
+        }
+        let repeated = tail(previous, overlap);
+        if !repeated.is_empty() && chunk.len() > repeated.len() && chunk.starts_with(repeated) {
+            text.push_str(&chunk[repeated.len()..]);
+        } else {
+            text.push_str("\n\n");
+            text.push_str(chunk);
+        }
+        previous = chunk;
+    }
+    text
+}
+
+/// Last `chars` characters of a chunk, the whole chunk when it is shorter
+fn tail(chunk: &str, chars: usize) -> &str {
+    if chars == 0 {
+        return "";
+    }
+    let start = chunk
+        .char_indices()
+        .rev()
+        .nth(chars - 1)
+        .map_or(0, |(i, _)| i);
+    &chunk[start..]
 }
 
 /// Process markdown files into chunks and save as JSONL
@@ -29,7 +181,11 @@ pub struct TextChunker {
     /// Overlap between chunks in characters
     chunk_overlap: usize,
     /// Text splitter instance
-    splitter: RecursiveCharacterTextSplitter,
+    splitter: Splitter,
+    /// Which splitter is in use
+    mode: SplitMode,
+    /// Docs version tagged on every chunk
+    version: Option<String>,
 }
 
 impl TextChunker {
@@ -62,48 +218,146 @@ impl TextChunker {
             output_file,
             chunk_size,
             chunk_overlap,
-            splitter,
+            splitter: Splitter::Recursive(splitter),
+            mode: SplitMode::Recursive,
+            version: None,
+        }
+    }
+
+    /// Tag every chunk with a docs version, e.g. `11.x`
+    pub fn with_version(mut self, version: Option<String>) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Docs version tagged on the chunks
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Select the splitter used for the files
+    pub fn with_mode(mut self, mode: SplitMode) -> Self {
+        self.splitter = match mode {
+            SplitMode::Recursive => Splitter::Recursive(
+                RecursiveCharacterTextSplitter::new()
+                    .with_chunk_size(self.chunk_size)
+                    .with_chunk_overlap(self.chunk_overlap),
+            ),
+            SplitMode::Markdown => Splitter::Markdown(
+                MarkdownTextSplitter::new()
+                    .with_chunk_size(self.chunk_size)
+                    .with_chunk_overlap(self.chunk_overlap),
+            ),
+        };
+        self.mode = mode;
+        self
+    }
+
+    /// Which splitter is in use
+    pub fn mode(&self) -> SplitMode {
+        self.mode
+    }
+
+    /// Directory containing markdown files to process
+    pub fn input_dir(&self) -> &Path {
+        &self.input_dir
+    }
+
+    /// Maximum size of chunks in characters
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Overlap between chunks in characters
+    pub fn chunk_overlap(&self) -> usize {
+        self.chunk_overlap
+    }
+
+    /// Splitter, chunk size and overlap in use
+    pub fn settings(&self) -> ChunkSettings {
+        ChunkSettings {
+            mode: self.mode,
+            chunk_size: self.chunk_size,
+            chunk_overlap: self.chunk_overlap,
         }
     }
 
-    /// Generate a unique ID based on file path
+    /// Generate a unique ID based on file path and version
     fn generate_uid(&self, file_path: &Path) -> String {
-        let path_str = file_path.to_string_lossy();
+        let path_str = match &self.version {
+            Some(version) => format!("{}@{}", file_path.to_string_lossy(), version),
+            None => file_path.to_string_lossy().to_string(),
+        };
         let mut hasher = Md5::new();
         hasher.update(path_str.as_bytes());
         format!("{:x}", hasher.finalize())
     }
 
+    /// Generate a content hash used to detect changed files between runs
+    pub fn content_hash(content: &str) -> String {
+        let mut hasher = Md5::new();
+        hasher.update(content.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
     /// Process a single markdown file into chunks
     pub fn process_file(&self, file_path: &Path) -> Result<Vec<TextChunk>> {
         // Read the file content
         let content = fs::read_to_string(file_path)
             .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
 
+        Ok(self.process_content(file_path, &content))
+    }
+
+    /// Split already loaded file content into chunks
+    pub fn process_content(&self, file_path: &Path, content: &str) -> Vec<TextChunk> {
         // Generate a unique ID based on file path
         let uid = self.generate_uid(file_path);
 
-        // Split content into chunks using RecursiveCharacterTextSplitter
-        let chunks = self.splitter.split_text(&content);
+        // Split content into chunks using the configured splitter
+        let chunks = self.splitter.split_text(content);
+        let headings = headings(content);
 
         // Create TextChunk objects for each chunk
         let mut result = Vec::new();
+        // chunk 按顺序出现在原文中, 用第一行定位它在原文中的位置
+        let mut cursor = 0;
         for (i, chunk) in chunks.into_iter().enumerate() {
             // Skip empty chunks
             if chunk.trim().is_empty() {
                 continue;
             }
 
+            let first_line = chunk.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+            if let Some(pos) = content[cursor..].find(first_line) {
+                cursor += pos;
+            }
+            let (section, anchor) = section_at(&headings, cursor);
+
             let chunk_data = TextChunk {
                 id: format!("{}-{}", uid, i),
                 text: chunk,
                 source: file_path.to_string_lossy().to_string(),
+                section,
+                anchor,
+                version: self.version.clone(),
             };
             result.push(chunk_data);
         }
 
         println!("Processed {}: {} chunks", file_path.display(), result.len());
-        Ok(result)
+        result
+    }
+
+    /// List all markdown files in the input directory
+    pub fn markdown_files(&self) -> Vec<PathBuf> {
+        WalkDir::new(&self.input_dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .map(|entry| entry.into_path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "md"))
+            .collect()
     }
 
     /// Process all markdown files in the input directory
@@ -112,21 +366,14 @@ impl TextChunker {
         let mut file_count = 0;
 
         // Walk through the input directory
-        for entry in WalkDir::new(&self.input_dir)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
-            if path.is_file() && path.extension().is_some_and(|ext| ext == "md") {
-                match self.process_file(path) {
-                    Ok(chunks) => {
-                        all_chunks.extend(chunks);
-                        file_count += 1;
-                    }
-                    Err(e) => {
-                        eprintln!("Error processing {}: {}", path.display(), e);
-                    }
+        for path in self.markdown_files() {
+            match self.process_file(&path) {
+                Ok(chunks) => {
+                    all_chunks.extend(chunks);
+                    file_count += 1;
+                }
+                Err(e) => {
+                    eprintln!("Error processing {}: {}", path.display(), e);
                 }
             }
         }
@@ -187,9 +434,71 @@ impl TextChunker {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_section_breadcrumbs() {
+        let content = "# Eloquent: Relationships\n\n<a name=\"many-to-many\"></a>\n## Many To Many\n\nIntro.\n\n```bash\n# not a heading\n```\n\n### Filtering Via Intermediate Table Columns\n\nUse wherePivot.\n\n## Polymorphic\n\nMorph text.";
+        let tc = TextChunker::new("docs", 70, 0).with_mode(SplitMode::Markdown);
+        let chunks = tc.process_content(Path::new("docs/eloquent-relationships.md"), content);
+
+        let sections: Vec<(&str, Option<&str>)> = chunks
+            .iter()
+            .map(|c| (c.section.as_str(), c.anchor.as_deref()))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                ("Eloquent: Relationships", Some("eloquent-relationships")),
+                (
+                    "Eloquent: Relationships > Many To Many",
+                    Some("many-to-many")
+                ),
+                (
+                    "Eloquent: Relationships > Many To Many > Filtering Via Intermediate Table Columns",
+                    Some("filtering-via-intermediate-table-columns")
+                ),
+                ("Eloquent: Relationships > Polymorphic", Some("polymorphic")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stitch_chunks() {
+        let content = "Eloquent models are classes. Each model maps to a table, and the table name is plural by default.";
+        let splitter = RecursiveCharacterTextSplitter::new()
+            .with_chunk_size(40)
+            .with_chunk_overlap(15);
+        let chunks = splitter.split_text(content);
+        assert!(chunks.len() > 1);
+        let chunks = chunks.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+        assert_eq!(stitch_chunks(&chunks, 15), content);
+
+        assert_eq!(
+            neighbour_ids("abc-1", 2, 1).unwrap(),
+            vec!["abc-0", "abc-1", "abc-2"]
+        );
+        assert!(neighbour_ids("abc", 1, 1).is_none());
+
+        // 没有重叠的块用空行拼接, 碰巧首尾相同的文字也不会被吞掉
+        assert_eq!(
+            stitch_chunks(&["# Title", "Body text"], 0),
+            "# Title\n\nBody text"
+        );
+        assert_eq!(
+            stitch_chunks(
+                &["use Illuminate\\Support\\Facades\\Route", "Route::get('/')"],
+                20
+            ),
+            "use Illuminate\\Support\\Facades\\Route\n\nRoute::get('/')"
+        );
+    }
+
     #[test]
     fn test_save() {
-        let tc = TextChunker::new("/Users/fyyx/Documents/laravel-comments-documentation", 400, 20);
+        let tc = TextChunker::new(
+            "/Users/fyyx/Documents/laravel-comments-documentation",
+            400,
+            20,
+        );
         assert!(tc.run().is_ok());
     }
 }
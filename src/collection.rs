@@ -0,0 +1,156 @@
+use anyhow::{Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// Longest collection name accepted, leaves room for the `_metadata` style suffixes
+const MAX_NAME_LEN: usize = 64;
+
+/// Global tables sharing the database with the collections
+const RESERVED_NAMES: &[&str] = &[
+    "collections",
+    "sync_state",
+    "query_log",
+    "query_feedback",
+    "schema_migrations",
+    "chunk_settings",
+];
+
+/// Suffixes of the companion tables and indexes of a collection, a name ending
+/// in one of them could clash with the tables of another collection
+const COMPANION_SUFFIXES: &[&str] = &[
+    "fts",
+    "metadata",
+    "metadata_chunk_id",
+    "metadata_source",
+    "metadata_v1",
+    "manifest",
+    "manifest_old",
+    "pages",
+];
+
+/// Name of a collection, checked to be a plain SQL identifier
+/// (`[A-Za-z_][A-Za-z0-9_]*`) so it can never change the meaning of a query
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CollectionName(String);
+
+/// Quotes an SQL identifier
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl CollectionName {
+    pub fn new(name: &str) -> Result<Self> {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid || name.len() > MAX_NAME_LEN {
+            bail!(
+                "invalid collection name `{}`, expected letters, digits and underscores, at most {} characters",
+                name,
+                MAX_NAME_LEN
+            );
+        }
+        // sqlite_ 开头的表名是 SQLite 保留的
+        if name.to_ascii_lowercase().starts_with("sqlite_") {
+            bail!("invalid collection name `{}`, sqlite_ is reserved", name);
+        }
+        let lower = name.to_ascii_lowercase();
+        if RESERVED_NAMES.contains(&lower.as_str()) {
+            bail!(
+                "invalid collection name `{}`, the table is used internally",
+                name
+            );
+        }
+        if let Some(suffix) = COMPANION_SUFFIXES
+            .iter()
+            .find(|suffix| lower.ends_with(&format!("_{}", suffix)))
+        {
+            bail!(
+                "invalid collection name `{}`, names may not end in `_{}`",
+                name,
+                suffix
+            );
+        }
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Quoted name of the vec0 table
+    pub fn table(&self) -> String {
+        quote_ident(&self.0)
+    }
+
+    /// Quoted name of a companion table, e.g. `metadata` for `{name}_metadata`
+    pub fn table_for(&self, suffix: &str) -> String {
+        quote_ident(&self.table_name(suffix))
+    }
+
+    /// Unquoted name of a companion table, for lookups in `sqlite_master`
+    pub fn table_name(&self, suffix: &str) -> String {
+        format!("{}_{}", self.0, suffix)
+    }
+}
+
+impl fmt::Display for CollectionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for CollectionName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::new(s)
+    }
+}
+
+impl AsRef<str> for CollectionName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for CollectionName {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_name() {
+        let name = CollectionName::new("laravel_docs").unwrap();
+        assert_eq!(name.table(), "\"laravel_docs\"");
+        assert_eq!(name.table_for("metadata"), "\"laravel_docs_metadata\"");
+        assert!(CollectionName::new("_private2").is_ok());
+
+        for bad in [
+            "",
+            "users; DROP TABLE collections",
+            "a\"b",
+            "docs-v2",
+            "1docs",
+            "sqlite_master",
+            "query_log",
+            "Chunk_Settings",
+            "docs_fts",
+            "docs_metadata",
+            "docs_metadata_v1",
+            "docs_manifest",
+            "docs_pages",
+            &"x".repeat(65),
+        ] {
+            assert!(CollectionName::new(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+}
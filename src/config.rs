@@ -0,0 +1,376 @@
+use crate::chunker::DEFAULT_CHUNK_OVERLAP;
+use crate::collection::CollectionName;
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Built-in registry used when no config file is given
+pub const DEFAULT_CONFIG: &str = include_str!("../laravel-docs.example.toml");
+
+/// Embedding model used when a tool does not name one
+pub const DEFAULT_MODEL: &str = "AllMiniLML6V2";
+
+/// Number of hits returned when neither the caller nor the config pass a limit
+pub const DEFAULT_LIMIT: usize = 20;
+
+/// Collection and tool registry loaded at startup
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Per collection settings keyed by collection name
+    #[serde(default)]
+    pub collections: BTreeMap<String, CollectionConfig>,
+    /// Dedicated search tools registered on the MCP server
+    #[serde(default)]
+    pub tools: Vec<ToolConfig>,
+    /// Named embedding backends, any other model name is looked up in fastembed
+    #[serde(default)]
+    pub embedders: BTreeMap<String, EmbedderConfig>,
+    /// Git repositories kept in sync by the `sync` command
+    #[serde(default)]
+    pub repos: Vec<RepoConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectionConfig {
+    /// Shown by `list_collections`
+    pub description: Option<String>,
+    /// Public page of a source file, `{page}` is replaced by the file stem
+    pub url_template: Option<String>,
+    /// Docs version searched when the caller does not pass one, e.g. `12.x`
+    pub current_version: Option<String>,
+    /// Embedder the collection is built and searched with, overrides the tools' model
+    pub model: Option<String>,
+    /// Rerank hits with a cross-encoder unless the call says otherwise
+    #[serde(default)]
+    pub rerank: bool,
+    /// fastembed reranker model, defaults to `BGERerankerBase`
+    pub reranker: Option<String>,
+    /// Hits fetched before reranking, defaults to 50
+    pub rerank_candidates: Option<usize>,
+    /// Named groups of pages a search can be restricted to, each a list of
+    /// page path prefixes, e.g. `testing = ["testing", "mocking", "dusk"]`
+    #[serde(default)]
+    pub tags: BTreeMap<String, Vec<String>>,
+}
+
+/// A docs repository synced into a collection
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoConfig {
+    /// Collection the markdown files are indexed into
+    pub collection: String,
+    /// Local clone path or `file://` url
+    pub url: String,
+    /// Branch to check out, e.g. `12.x`
+    pub branch: Option<String>,
+    /// Tag to check out instead of a branch
+    pub tag: Option<String>,
+    /// Docs directory inside the repository, defaults to the repository root
+    pub path: Option<PathBuf>,
+    /// Docs version the chunks are tagged with
+    pub version: Option<String>,
+    /// Maximum size of chunks in characters
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    /// Overlap between chunks in characters
+    #[serde(default = "default_chunk_overlap")]
+    pub chunk_overlap: usize,
+}
+
+fn default_chunk_size() -> usize {
+    400
+}
+
+fn default_chunk_overlap() -> usize {
+    DEFAULT_CHUNK_OVERLAP
+}
+
+/// Embedding backend, selected by the `backend` key
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum EmbedderConfig {
+    /// Local fastembed model, e.g. `AllMiniLML6V2` or `BGESmallENV15`
+    Fastembed { model: String },
+    /// OpenAI compatible embeddings endpoint
+    Openai {
+        url: String,
+        model: String,
+        dimension: usize,
+        /// Environment variable holding the api key
+        api_key_env: Option<String>,
+    },
+    /// Deterministic embeddings for tests
+    Mock { dimension: usize },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ToolConfig {
+    /// MCP tool name
+    pub name: String,
+    /// MCP tool description, this is what agents read to pick the tool
+    pub description: String,
+    /// Collection searched by the tool
+    pub collection: String,
+    /// Number of hits returned when the caller does not pass a limit
+    #[serde(default = "default_limit")]
+    pub default_limit: usize,
+    /// Embedder the collection was embedded with, a name from `[embedders]` or a fastembed model
+    #[serde(default = "default_model")]
+    pub model: String,
+}
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+fn default_model() -> String {
+    DEFAULT_MODEL.to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::parse(DEFAULT_CONFIG).expect("built-in config is valid")
+    }
+}
+
+impl Config {
+    /// Parse a TOML registry
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: Config = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load a TOML registry from disk
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config: {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid config: {}", path.display()))
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut models: HashMap<&str, &str> = HashMap::new();
+        for tool in &self.tools {
+            if tool.name.is_empty()
+                || !tool
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                bail!("tool name `{}` may only contain [A-Za-z0-9_-]", tool.name);
+            }
+            if !names.insert(tool.name.as_str()) {
+                bail!("tool `{}` is declared twice", tool.name);
+            }
+            if tool.default_limit == 0 {
+                bail!("tool `{}` has a default_limit of 0", tool.name);
+            }
+            CollectionName::new(&tool.collection)
+                .with_context(|| format!("tool `{}`", tool.name))?;
+            // 同一个集合只能用一个模型检索, 否则向量维度和语义都对不上
+            match models.insert(&tool.collection, &tool.model) {
+                Some(other) if other != tool.model => bail!(
+                    "collection `{}` is used with both `{}` and `{}` models",
+                    tool.collection,
+                    other,
+                    tool.model
+                ),
+                _ => {}
+            }
+        }
+        for (name, collection) in &self.collections {
+            CollectionName::new(name)?;
+            if collection.rerank_candidates == Some(0) {
+                bail!("collection `{}` has rerank_candidates of 0", name);
+            }
+            for (tag, prefixes) in &collection.tags {
+                if prefixes.is_empty() || prefixes.iter().any(|p| p.is_empty()) {
+                    bail!(
+                        "tag `{}` of collection `{}` needs at least one non-empty page prefix",
+                        tag,
+                        name
+                    );
+                }
+            }
+        }
+        let mut synced = HashSet::new();
+        for repo in &self.repos {
+            if repo.branch.is_some() == repo.tag.is_some() {
+                bail!("repo `{}` must set exactly one of branch or tag", repo.url);
+            }
+            CollectionName::new(&repo.collection)
+                .with_context(|| format!("repo `{}`", repo.url))?;
+            if !synced.insert((repo.collection.as_str(), repo.version.as_deref())) {
+                bail!(
+                    "collection `{}` version {:?} is synced from two repos",
+                    repo.collection,
+                    repo.version
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings of a collection, empty when it is not configured
+    pub fn collection(&self, name: &str) -> CollectionConfig {
+        self.collections.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Tool declared under the given name
+    pub fn tool(&self, name: &str) -> Option<&ToolConfig> {
+        self.tools.iter().find(|t| t.name == name)
+    }
+
+    /// Collections searched by the configured tools, they must exist for the server to be ready
+    pub fn searched_collections(&self) -> BTreeSet<&str> {
+        self.tools.iter().map(|t| t.collection.as_str()).collect()
+    }
+
+    /// Embedding model of a collection, taken from its settings or the tools that search it
+    pub fn model_for(&self, collection: &str) -> &str {
+        if let Some(model) = self
+            .collections
+            .get(collection)
+            .and_then(|c| c.model.as_deref())
+        {
+            return model;
+        }
+        self.tools
+            .iter()
+            .find(|t| t.collection == collection)
+            .map(|t| t.model.as_str())
+            .unwrap_or(DEFAULT_MODEL)
+    }
+
+    /// Backend of a model name, names missing from `[embedders]` are fastembed models
+    pub fn embedder(&self, model: &str) -> EmbedderConfig {
+        self.embedders
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| EmbedderConfig::Fastembed {
+                model: model.to_string(),
+            })
+    }
+
+    /// Default limit of a collection, taken from the tools that search it
+    pub fn limit_for(&self, collection: &str) -> usize {
+        self.tools
+            .iter()
+            .find(|t| t.collection == collection)
+            .map(|t| t.default_limit)
+            .unwrap_or(DEFAULT_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = Config::default();
+        assert_eq!(config.tools.len(), 5);
+        let tool = config.tool("get_laravel_context").unwrap();
+        assert_eq!(tool.collection, "laravel_docs");
+        assert_eq!(tool.default_limit, DEFAULT_LIMIT);
+        assert_eq!(config.model_for("laravel_docs"), DEFAULT_MODEL);
+        assert_eq!(
+            config.collection("laravel_docs").url_template.as_deref(),
+            Some("https://laravel.com/docs/{page}")
+        );
+        assert!(config.collection("laravel_docs").tags["testing"].contains(&"mocking".to_string()));
+        assert!(Config::parse("[collections.docs.tags]\ntesting = []").is_err());
+    }
+
+    #[test]
+    fn test_rejects_invalid_tools() {
+        let duplicate = r#"
+            [[tools]]
+            name = "a"
+            description = "a"
+            collection = "x"
+
+            [[tools]]
+            name = "a"
+            description = "b"
+            collection = "y"
+        "#;
+        assert!(Config::parse(duplicate).is_err());
+
+        let mixed_models = r#"
+            [[tools]]
+            name = "a"
+            description = "a"
+            collection = "x"
+
+            [[tools]]
+            name = "b"
+            description = "b"
+            collection = "x"
+            model = "BGESmallENV15"
+        "#;
+        assert!(Config::parse(mixed_models).is_err());
+
+        let bad_name = r#"
+            [[tools]]
+            name = "get docs"
+            description = "a"
+            collection = "x"
+        "#;
+        assert!(Config::parse(bad_name).is_err());
+
+        let bad_collection = r#"
+            [[tools]]
+            name = "a"
+            description = "a"
+            collection = "docs; DROP TABLE collections"
+        "#;
+        assert!(Config::parse(bad_collection).is_err());
+    }
+
+    #[test]
+    fn test_embedders() {
+        let config = Config::parse(
+            r#"
+            [collections.internal]
+            model = "local-openai"
+            rerank = true
+            rerank_candidates = 30
+
+            [embedders.local-openai]
+            backend = "openai"
+            url = "http://localhost:8080/v1/embeddings"
+            model = "nomic-embed-text"
+            dimension = 768
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.model_for("internal"), "local-openai");
+        let internal = config.collection("internal");
+        assert!(internal.rerank);
+        assert_eq!(internal.rerank_candidates, Some(30));
+        assert!(!config.collection("laravel_docs").rerank);
+        assert_eq!(
+            config.embedder("local-openai"),
+            EmbedderConfig::Openai {
+                url: "http://localhost:8080/v1/embeddings".to_string(),
+                model: "nomic-embed-text".to_string(),
+                dimension: 768,
+                api_key_env: None,
+            }
+        );
+        assert_eq!(
+            config.embedder(DEFAULT_MODEL),
+            EmbedderConfig::Fastembed {
+                model: DEFAULT_MODEL.to_string()
+            }
+        );
+    }
+}
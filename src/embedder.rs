@@ -0,0 +1,295 @@
+use crate::config::EmbedderConfig;
+use anyhow::{Context, Result, anyhow, bail};
+use fastembed::{InitOptions, TextEmbedding};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Turns texts into embeddings for a `Vectorizer`
+pub trait Embedder: Send + Sync {
+    /// Model name recorded with every collection built by this embedder
+    fn model(&self) -> &str;
+
+    /// Length of the produced embeddings
+    fn dimension(&self) -> usize;
+
+    /// Embeds a batch of texts, one embedding per text in the same order
+    fn embed(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>>;
+}
+
+/// Builds the embedder described by a config entry
+pub fn from_config(config: &EmbedderConfig, cache_dir: &Path) -> Result<Arc<dyn Embedder>> {
+    Ok(match config {
+        EmbedderConfig::Fastembed { model } => Arc::new(FastEmbedder::new(model, cache_dir)?),
+        EmbedderConfig::Openai {
+            url,
+            model,
+            dimension,
+            api_key_env,
+        } => {
+            let mut embedder = HttpEmbedder::new(url, model, *dimension);
+            if let Some(var) = api_key_env {
+                let key = std::env::var(var)
+                    .with_context(|| format!("missing api key, set the {} variable", var))?;
+                embedder = embedder.with_api_key(&key);
+            }
+            Arc::new(embedder)
+        }
+        EmbedderConfig::Mock { dimension } => Arc::new(MockEmbedder::new(*dimension)),
+    })
+}
+
+/// Local fastembed model
+pub struct FastEmbedder {
+    name: String,
+    dimension: usize,
+    model: TextEmbedding,
+}
+
+impl FastEmbedder {
+    /// Loads a supported fastembed model by name, e.g. `AllMiniLML6V2` or
+    /// `Qdrant/all-MiniLM-L6-v2-onnx`
+    pub fn new(name: &str, cache_dir: &Path) -> Result<Self> {
+        let info = TextEmbedding::list_supported_models()
+            .into_iter()
+            .find(|info| format!("{:?}", info.model) == name || info.model_code == name)
+            .ok_or_else(|| anyhow!("unknown embedding model `{}`", name))?;
+        let model = TextEmbedding::try_new(
+            InitOptions::new(info.model.clone())
+                .with_cache_dir(cache_dir.to_path_buf())
+                .with_show_download_progress(true),
+        )?;
+
+        Ok(Self {
+            name: format!("fastembed/{:?}", info.model),
+            dimension: info.dim,
+            model,
+        })
+    }
+}
+
+impl Embedder for FastEmbedder {
+    fn model(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>> {
+        self.model.embed(texts, None)
+    }
+}
+
+/// OpenAI compatible `/v1/embeddings` endpoint
+pub struct HttpEmbedder {
+    url: String,
+    name: String,
+    /// Model id sent to the endpoint
+    model_id: String,
+    dimension: usize,
+    api_key: Option<String>,
+    agent: ureq::Agent,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+    #[serde(default)]
+    index: usize,
+}
+
+impl HttpEmbedder {
+    pub fn new(url: &str, model: &str, dimension: usize) -> Self {
+        Self {
+            url: url.to_string(),
+            name: format!("openai/{}", model),
+            model_id: model.to_string(),
+            dimension,
+            api_key: None,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    /// Sent as a bearer token
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+}
+
+impl Embedder for HttpEmbedder {
+    fn model(&self) -> &str {
+        &self.name
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>> {
+        let mut request = self.agent.post(&self.url);
+        if let Some(key) = &self.api_key {
+            request = request.set("Authorization", &format!("Bearer {}", key));
+        }
+        let response: EmbeddingResponse = request
+            .send_json(json!({ "model": self.model_id, "input": texts }))
+            .with_context(|| format!("embedding request to {} failed", self.url))?
+            .into_json()
+            .context("invalid embedding response")?;
+
+        let mut data = response.data;
+        if data.len() != texts.len() {
+            bail!(
+                "embedding endpoint returned {} embeddings for {} texts",
+                data.len(),
+                texts.len()
+            );
+        }
+        data.sort_by_key(|d| d.index);
+        data.into_iter()
+            .map(|d| {
+                if d.embedding.len() != self.dimension {
+                    bail!(
+                        "`{}` returned {} dimensions, expected {}",
+                        self.model_id,
+                        d.embedding.len(),
+                        self.dimension
+                    );
+                }
+                Ok(d.embedding)
+            })
+            .collect()
+    }
+}
+
+/// Deterministic character based embeddings, for tests
+pub struct MockEmbedder {
+    dimension: usize,
+}
+
+impl MockEmbedder {
+    pub fn new(dimension: usize) -> Self {
+        Self { dimension }
+    }
+
+    pub fn mock_embed(&self, text: &str) -> Vec<f32> {
+        let mut embedding = vec![0.0; self.dimension];
+
+        for (i, c) in text.chars().enumerate() {
+            let pos = i % self.dimension;
+            embedding[pos] += (c as u32 % 256) as f32 / 128.0 - 1.0;
+        }
+
+        // Normalize the embedding
+        let magnitude: f32 = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if magnitude > 0.0 {
+            for value in &mut embedding {
+                *value /= magnitude;
+            }
+        }
+
+        embedding
+    }
+}
+
+impl Embedder for MockEmbedder {
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.into_iter().map(|t| self.mock_embed(t)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers a single embeddings request and returns the request body
+    fn stub_server(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/embeddings", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length")
+                {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .unwrap();
+            String::from_utf8(body).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_http_embedder() {
+        let (url, server) = stub_server(
+            r#"{"object":"list","data":[{"index":1,"embedding":[0.0,1.0]},{"index":0,"embedding":[1.0,0.0]}]}"#,
+        );
+        let embedder = HttpEmbedder::new(&url, "stub-model", 2);
+        let embeddings = embedder.embed(vec!["a", "b"]).unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+        assert_eq!(embedder.model(), "openai/stub-model");
+
+        let body: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(body, json!({ "model": "stub-model", "input": ["a", "b"] }));
+
+        // 维度不一致时拒绝
+        let (url, server) = stub_server(r#"{"data":[{"index":0,"embedding":[1.0,0.0,0.0]}]}"#);
+        assert!(
+            HttpEmbedder::new(&url, "stub-model", 2)
+                .embed(vec!["a"])
+                .is_err()
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_mock_embedder_is_deterministic() {
+        let embedder = MockEmbedder::new(8);
+        let a = embedder.embed(vec!["whereBelongsTo", "HasUuids"]).unwrap();
+        let b = embedder.embed(vec!["whereBelongsTo", "HasUuids"]).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a[0], a[1]);
+        let norm: f32 = a[0].iter().map(|x| x * x).sum();
+        assert!((norm - 1.0).abs() < 1e-5);
+    }
+}
@@ -22,6 +22,9 @@ pub enum AppError {
     #[error("Bad request: {0}")]
     BadRequest(String),
 
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     #[error("Internal server error: {0}")]
     InternalServerError(String),
 
@@ -29,6 +32,21 @@ pub enum AppError {
     SqliteVectorError(String),
 }
 
+impl AppError {
+    /// Variant name, used as the `kind` label of the error metric
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::Rmcp(_) => "Rmcp",
+            AppError::Io(_) => "Io",
+            AppError::NotFound(_) => "NotFound",
+            AppError::BadRequest(_) => "BadRequest",
+            AppError::Forbidden(_) => "Forbidden",
+            AppError::InternalServerError(_) => "InternalServerError",
+            AppError::SqliteVectorError(_) => "SqliteVectorError",
+        }
+    }
+}
+
 impl From<anyhow::Error> for AppError {
     fn from(error: anyhow::Error) -> Self {
         AppError::InternalServerError(error.to_string())
@@ -59,6 +77,9 @@ impl IntoContents for AppError {
             AppError::BadRequest(msg) => {
                 vec![rmcp::model::Content::text(format!("Bad request: {}", msg))]
             }
+            AppError::Forbidden(msg) => {
+                vec![rmcp::model::Content::text(format!("Forbidden: {}", msg))]
+            }
             AppError::InternalServerError(msg) => vec![rmcp::model::Content::text(format!(
                 "Internal server error: {}",
                 msg
@@ -77,7 +98,10 @@ impl IntoCallToolResult for AppResultWrapper {
     fn into_call_tool_result(self) -> Result<rmcp::model::CallToolResult, ErrorData> {
         match self.0 {
             Ok(res) => Ok(res),
-            Err(e) => e.into_call_tool_result(),
+            Err(e) => {
+                crate::metrics::global().error(e.kind());
+                e.into_call_tool_result()
+            }
         }
     }
 }
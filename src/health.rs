@@ -0,0 +1,137 @@
+use axum::{Router, http::StatusCode, routing::get};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Liveness of the server: whether the models are loaded, whether it is
+/// shutting down and how many tool calls are running
+#[derive(Debug, Default)]
+pub struct Health {
+    ready: AtomicBool,
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Held while a tool call runs, see [`Health::start_call`]
+pub struct CallGuard(Arc<Health>);
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks the models as loaded
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    /// Loaded and not shutting down
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst) && !self.is_draining()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Tool calls currently running
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Counts a tool call until the guard is dropped, `None` once draining started
+    pub fn start_call(self: &Arc<Self>) -> Option<CallGuard> {
+        if self.is_draining() {
+            return None;
+        }
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Some(CallGuard(self.clone()))
+    }
+
+    /// Refuses new tool calls and waits for the running ones, returns false
+    /// when some are still running after `timeout`
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.draining.store(true, Ordering::SeqCst);
+        tokio::time::timeout(timeout, async {
+            loop {
+                // 先注册等待再检查计数, 避免错过最后一次通知
+                let idle = self.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+}
+
+/// `/healthz` answers as long as the process serves requests, `/readyz` only
+/// once the models are loaded and `check` passes, e.g. the database has the
+/// expected collections
+pub fn router<F>(health: Arc<Health>, check: F) -> Router
+where
+    F: Fn() -> Result<(), String> + Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route(
+            "/readyz",
+            get(move || {
+                let health = health.clone();
+                let check = check.clone();
+                async move {
+                    if health.is_draining() {
+                        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down".to_string());
+                    }
+                    if !health.is_ready() {
+                        return (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "loading models".to_string(),
+                        );
+                    }
+                    match tokio::task::spawn_blocking(check).await {
+                        Ok(Ok(())) => (StatusCode::OK, "ready".to_string()),
+                        Ok(Err(reason)) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+                        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                    }
+                }
+            }),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain() {
+        let health = Arc::new(Health::new());
+        assert!(!health.is_ready());
+        health.set_ready();
+        assert!(health.is_ready());
+
+        let call = health.start_call().unwrap();
+        assert_eq!(health.in_flight(), 1);
+        assert!(!health.drain(Duration::from_millis(10)).await);
+        assert!(!health.is_ready());
+        assert!(health.start_call().is_none());
+
+        let waiting = health.clone();
+        let drained = tokio::spawn(async move { waiting.drain(Duration::from_secs(5)).await });
+        drop(call);
+        assert!(drained.await.unwrap());
+        assert_eq!(health.in_flight(), 0);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::Vectorizer;
+use crate::chunker::{ChunkSettings, SplitMode, TextChunker};
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Summary of a finished ingest run
+#[derive(Debug, Default)]
+pub struct IngestSummary {
+    /// Collection the chunks were written to
+    pub collection: String,
+    /// Docs directory that was walked
+    pub source: PathBuf,
+    /// Docs version the chunks were tagged with
+    pub version: Option<String>,
+    /// Markdown files found in the docs directory
+    pub files: usize,
+    /// Files indexed for the first time
+    pub added: usize,
+    /// Files whose content hash changed since the last run
+    pub changed: usize,
+    /// Files that disappeared since the last run
+    pub removed: usize,
+    /// Files skipped because their content hash did not change
+    pub unchanged: usize,
+    /// Chunks embedded and stored in this run
+    pub chunks: usize,
+    /// Rows deleted for changed or removed files
+    pub deleted: usize,
+    /// Rows in the collection after the run
+    pub total: usize,
+    /// Splitter used to cut the files
+    pub split_mode: SplitMode,
+    /// Chunk size used by the splitter
+    pub chunk_size: usize,
+    /// Chunk overlap used by the splitter
+    pub chunk_overlap: usize,
+    /// Settings the version was chunked with before, when they changed and
+    /// every file was re-indexed
+    pub rechunked: Option<ChunkSettings>,
+    /// Wall time of the whole run
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for IngestSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Ingest finished")?;
+        writeln!(f, "  collection : {}", self.collection)?;
+        writeln!(f, "  source     : {}", self.source.display())?;
+        if let Some(version) = &self.version {
+            writeln!(f, "  version    : {}", version)?;
+        }
+        writeln!(
+            f,
+            "  files      : {} (added {}, changed {}, removed {}, unchanged {})",
+            self.files, self.added, self.changed, self.removed, self.unchanged
+        )?;
+        writeln!(
+            f,
+            "  chunks     : {} embedded, {} deleted, {} total",
+            self.chunks, self.deleted, self.total
+        )?;
+        writeln!(
+            f,
+            "  splitter   : {} size {} / overlap {}",
+            self.split_mode, self.chunk_size, self.chunk_overlap
+        )?;
+        if let Some(previous) = &self.rechunked {
+            writeln!(f, "  rechunked  : was {}", previous)?;
+        }
+        write!(f, "  elapsed    : {:.2?}", self.elapsed)
+    }
+}
+
+/// Chunks a docs directory, embeds the chunks and stores them in a collection
+pub struct Ingestor {
+    chunker: TextChunker,
+    vectorizer: Vectorizer,
+    full: bool,
+}
+
+impl Ingestor {
+    pub fn new(chunker: TextChunker, vectorizer: Vectorizer) -> Self {
+        Self {
+            chunker,
+            vectorizer,
+            full: false,
+        }
+    }
+
+    /// Remove the docs version and re-embed every file instead of only the
+    /// changed ones, other versions of the collection are kept
+    pub fn with_full_rebuild(mut self, full: bool) -> Self {
+        self.full = full;
+        self
+    }
+
+    /// Bring the collection in sync with the docs directory
+    pub fn run(&mut self) -> Result<IngestSummary> {
+        self.index(None)
+    }
+
+    /// Re-index only the given files, e.g. the ones changed between two commits.
+    /// Files that no longer exist are removed from the collection
+    pub fn run_files(&mut self, paths: Vec<PathBuf>) -> Result<IngestSummary> {
+        self.index(Some(paths))
+    }
+
+    fn index(&mut self, mut only: Option<Vec<PathBuf>>) -> Result<IngestSummary> {
+        let started = Instant::now();
+
+        let version = self.chunker.version().map(|v| v.to_string());
+        // 多个版本共用一个集合, 全量重建只清掉当前版本
+        if self.full {
+            self.vectorizer.clean_version(version.as_deref())?;
+        }
+        self.vectorizer.create_table()?;
+
+        if !self.vectorizer.has_manifest()? && self.vectorizer.count()? > 0 {
+            // 旧版本导入的集合没有 manifest, 无法知道行属于哪个文件, 只能整体重建这个版本
+            println!("Collection has rows but no manifest, rebuilding the version from scratch");
+            self.vectorizer.clean_version(version.as_deref())?;
+            self.vectorizer.create_table()?;
+            only = None;
+        }
+        if self.full {
+            only = None;
+        }
+        // 切分参数变了, 内容没变的文件也要按新参数重新切分
+        let settings = self.chunker.settings();
+        let rechunked = self
+            .vectorizer
+            .chunk_settings(version.as_deref())?
+            .filter(|previous| *previous != settings);
+        if let Some(previous) = &rechunked {
+            println!(
+                "Chunker settings changed from {} to {}, re-indexing every file",
+                previous, settings
+            );
+            only = None;
+        }
+        // 每个版本各自维护 manifest, 导入一个版本不会影响其它版本
+        let mut manifest = self.vectorizer.manifest(version.as_deref())?;
+
+        let mut summary = IngestSummary {
+            collection: self.vectorizer.collection().to_string(),
+            source: self.chunker.input_dir().to_path_buf(),
+            version: version.clone(),
+            split_mode: self.chunker.mode(),
+            chunk_size: self.chunker.chunk_size(),
+            chunk_overlap: self.chunker.chunk_overlap(),
+            rechunked,
+            ..Default::default()
+        };
+
+        let partial = only.is_some();
+        let files = only.unwrap_or_else(|| self.chunker.markdown_files());
+        for path in files {
+            let source = path.to_string_lossy().to_string();
+            let previous = manifest.remove(&source);
+            if partial && !path.exists() {
+                if previous.is_some() {
+                    summary.deleted += self
+                        .vectorizer
+                        .delete_by_source(version.as_deref(), &source)?;
+                    summary.removed += 1;
+                }
+                continue;
+            }
+            summary.files += 1;
+
+            let content = match fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(e) => {
+                    // 读取失败时保留旧数据, 不当作删除处理
+                    eprintln!("Error processing {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let content_hash = TextChunker::content_hash(&content);
+            // 原文每次都写入, 这样升级前导入的集合也能在下次导入时补齐页面
+            let relative = path
+                .strip_prefix(self.chunker.input_dir())
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+            self.vectorizer
+                .store_page(version.as_deref(), &source, &relative, &content)?;
+
+            match &previous {
+                Some(entry)
+                    if entry.content_hash == content_hash && summary.rechunked.is_none() =>
+                {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                Some(entry) => {
+                    summary.changed += 1;
+                    summary.deleted += entry.rowids.len();
+                }
+                None => summary.added += 1,
+            }
+
+            // 与 jsonl 的行格式一致, 写入时拆成 metadata 表的各列
+            let chunks = self.chunker.process_content(&path, &content);
+            let lines = chunks
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<Result<Vec<_>, _>>()
+                .context("Failed to serialize chunk to JSON")?;
+
+            let rowids = self.vectorizer.index_source(
+                version.as_deref(),
+                &source,
+                &content_hash,
+                lines.iter().map(|l| l.as_str()).collect(),
+            )?;
+            summary.chunks += rowids.len();
+        }
+
+        // 全量扫描时 manifest 中剩下的都是已经被删除的文件
+        for source in manifest.into_keys().filter(|_| !partial) {
+            summary.deleted += self
+                .vectorizer
+                .delete_by_source(version.as_deref(), &source)?;
+            summary.removed += 1;
+        }
+
+        self.vectorizer
+            .set_chunk_settings(version.as_deref(), &settings)?;
+        summary.total = self.vectorizer.count()?;
+        summary.elapsed = started.elapsed();
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedder::MockEmbedder;
+    use crate::vectorizer::SearchFilter;
+    use std::sync::Arc;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}_{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_rechunks_on_changed_settings() {
+        let db = temp_path("test_rechunks_on_changed_settings.db3");
+        let docs = temp_path("test_rechunks_on_changed_settings_docs");
+        let _ = fs::remove_file(&db);
+        let _ = fs::remove_dir_all(&docs);
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(
+            docs.join("queues.md"),
+            "# Queues\n\nDispatching jobs onto the queue.\n\nJob batching lets you run a batch of jobs.\n",
+        )
+        .unwrap();
+
+        let ingest = |chunk_size: usize| {
+            let vectorizer = Vectorizer::new(&db, "docs", Arc::new(MockEmbedder::new(4))).unwrap();
+            let chunker = TextChunker::new(&docs, chunk_size, 0).with_mode(SplitMode::Markdown);
+            Ingestor::new(chunker, vectorizer).run().unwrap()
+        };
+
+        let first = ingest(400);
+        assert_eq!((first.added, first.chunks), (1, 1));
+        assert!(first.rechunked.is_none());
+        assert_eq!(ingest(400).unchanged, 1);
+
+        // 内容没变, 但切分参数变了, 文件按新参数重新切分
+        let smaller = ingest(40);
+        assert_eq!(smaller.changed, 1);
+        assert!(smaller.chunks > 1);
+        assert_eq!(smaller.rechunked.map(|s| s.chunk_size), Some(400));
+        assert_eq!(ingest(40).unchanged, 1);
+
+        let _ = fs::remove_file(&db);
+        let _ = fs::remove_dir_all(&docs);
+    }
+
+    #[test]
+    fn test_full_rebuild_keeps_other_versions() {
+        let db = temp_path("test_full_rebuild_keeps_other_versions.db3");
+        let docs = temp_path("test_full_rebuild_keeps_other_versions_docs");
+        let _ = fs::remove_file(&db);
+        let _ = fs::remove_dir_all(&docs);
+        for (version, text) in [
+            ("10.x", "Job batching in version ten."),
+            ("11.x", "Job batching in version eleven."),
+        ] {
+            fs::create_dir_all(docs.join(version)).unwrap();
+            fs::write(docs.join(version).join("queues.md"), text).unwrap();
+        }
+
+        let vectorizer = Vectorizer::new(&db, "docs", Arc::new(MockEmbedder::new(4))).unwrap();
+        let ingest = |version: &str, full: bool| {
+            let chunker =
+                TextChunker::new(docs.join(version), 400, 0).with_version(Some(version.into()));
+            Ingestor::new(chunker, vectorizer.clone())
+                .with_full_rebuild(full)
+                .run()
+                .unwrap()
+        };
+        ingest("10.x", false);
+        ingest("11.x", false);
+        vectorizer
+            .set_indexed_commit(Some("10.x"), "repo", "abc")
+            .unwrap();
+        vectorizer
+            .set_indexed_commit(Some("11.x"), "repo", "def")
+            .unwrap();
+
+        let rebuilt = ingest("10.x", true);
+        assert_eq!((rebuilt.added, rebuilt.total), (1, 2));
+        assert_eq!(
+            vectorizer.versions().unwrap(),
+            vec![(Some("10.x".to_string()), 1), (Some("11.x".to_string()), 1)]
+        );
+        let eleven = SearchFilter::default().with_version(Some("11.x".to_string()));
+        let hits = vectorizer
+            .keyword_search("batching", Some(5), &eleven)
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(
+            hits[0].chunk.as_ref().unwrap().text,
+            "Job batching in version eleven."
+        );
+        assert_eq!(
+            vectorizer
+                .search("batching", Some(5), &eleven)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(vectorizer.manifest(Some("11.x")).unwrap().len(), 1);
+        // 只有重建的版本需要重新比对, 其它版本的同步提交保持不变
+        assert_eq!(vectorizer.indexed_commit(Some("10.x")).unwrap(), None);
+        assert_eq!(
+            vectorizer.indexed_commit(Some("11.x")).unwrap().as_deref(),
+            Some("def")
+        );
+
+        let _ = fs::remove_file(&db);
+        let _ = fs::remove_dir_all(&docs);
+    }
+}
@@ -1,6 +1,21 @@
+pub mod auth;
 pub mod chunker;
+pub mod collection;
+pub mod config;
+pub mod embedder;
 pub mod error;
+pub mod health;
+pub mod ingest;
+pub mod markdown_splitter;
+pub mod metrics;
+pub mod migrations;
+pub mod prompts;
+pub mod reranker;
+pub mod sse_server;
+pub mod streamable_http;
+pub mod sync;
 pub mod text_splitter;
+pub mod transport;
 pub mod vectorizer;
 
 pub use vectorizer::Vectorizer;
@@ -1,44 +1,112 @@
+use anyhow::anyhow;
 use clap::{Parser, Subcommand};
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
 use laravel_docs_mcp::{
     Vectorizer,
+    auth::{Auth, Client, require_bearer},
+    chunker::{DEFAULT_CHUNK_OVERLAP, SplitMode, TextChunker, neighbour_ids, stitch_chunks},
+    collection::CollectionName,
+    config::{CollectionConfig, Config},
+    embedder::{self, Embedder},
     error::{AppError, AppResultWrapper},
+    health::{self, Health},
+    ingest::Ingestor,
+    metrics,
+    prompts::{self, Citation, LaravelPrompt, PROMPT_HITS},
+    reranker::{DEFAULT_RERANKER, FastReranker, RERANK_CANDIDATES, Reranker},
+    sse_server::SseServer,
+    streamable_http::StreamableHttpServer,
+    sync::RepoCheckout,
+    vectorizer::{
+        LoggedHit, QueryLogEntry, SearchFilter, SearchHit, SearchMode, SqliteVector, StoredChunk,
+    },
 };
 use rmcp::{
-    ServerHandler, ServiceExt,
+    RoleServer, ServerHandler, ServiceExt,
+    handler::server::tool::{
+        IntoCallToolResult, ToolCallContext, cached_schema_for_type, parse_json_object,
+    },
     model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+        AnnotateAble, CallToolRequestParam, CallToolResult, Content, GetPromptRequestParam,
+        GetPromptResult, Implementation, ListPromptsResult, ListResourcesResult, ListToolsResult,
+        PaginatedRequestParam, PromptMessage, PromptMessageRole, ProtocolVersion, RawResource,
+        ReadResourceRequestParam, ReadResourceResult, ResourceContents, ServerCapabilities,
+        ServerInfo, Tool,
     },
+    schemars::{self, JsonSchema},
+    service::RequestContext,
     tool,
-    transport::{SseServer, sse_server::SseServerConfig, stdio},
+    transport::stdio,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, Mutex, MutexGuard, OnceLock};
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    sync::Arc,
     vec,
 };
 use tokio::sync::RwLock;
 
-pub(crate) static MODEL: LazyLock<Arc<TextEmbedding>> = LazyLock::new(|| {
-    let arg = Args::parse();
-    let model_path = arg
-        .model_path
-        .unwrap_or_else(|| format!("{}/.fastembed_cache", std::env::var("HOME").unwrap()).into());
+/// Upper bound for the `limit` tool parameter
+const MAX_LIMIT: usize = 100;
+
+/// Upper bound for the `before` / `after` parameters of get_surrounding_chunks
+const MAX_SURROUNDING: usize = 10;
+
+/// How long a SIGTERM waits for running tool calls before the sessions are closed
+const DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(25);
+
+/// Scheme of the doc page resources, `docs://{collection}/{path}?version={version}`
+const RESOURCE_SCHEME: &str = "docs://";
+
+/// Embedders built so far, keyed by model name
+static EMBEDDERS: LazyLock<std::sync::Mutex<HashMap<String, Arc<dyn Embedder>>>> =
+    LazyLock::new(Default::default);
+
+/// Where fastembed caches its models when MODEL_PATH is not set
+fn default_model_path() -> PathBuf {
+    match std::env::var("HOME") {
+        Ok(home) => Path::new(&home).join(".fastembed_cache"),
+        Err(_) => PathBuf::from(".fastembed_cache"),
+    }
+}
+
+/// Builds the embedder of a model name (e.g. `AllMiniLML6V2` or a name from `[embedders]`) once
+fn load_embedder(
+    config: &Config,
+    model_path: &Path,
+    name: &str,
+) -> anyhow::Result<Arc<dyn Embedder>> {
+    let mut embedders = EMBEDDERS.lock().map_err(|_| anyhow!("Mutex poisoned"))?;
+    if let Some(embedder) = embedders.get(name) {
+        return Ok(embedder.clone());
+    }
+
     // let model_path = "/Users/fyyx/Documents/rust_projects/rust-mcp-demo/~/.fastembed_cache";
-    Arc::new(
-        TextEmbedding::try_new(
-            InitOptions::new(EmbeddingModel::AllMiniLML6V2)
-                .with_cache_dir(model_path.into())
-                .with_show_download_progress(true),
-        )
-        .unwrap(),
-    )
-});
+    let embedder = embedder::from_config(&config.embedder(name), model_path)?;
+
+    embedders.insert(name.to_string(), embedder.clone());
+    Ok(embedder)
+}
+
+/// Rerankers built so far, keyed by model name
+static RERANKERS: LazyLock<std::sync::Mutex<HashMap<String, Arc<dyn Reranker>>>> =
+    LazyLock::new(Default::default);
+
+/// Loads a fastembed reranker by name once
+fn load_reranker(model_path: &Path, name: &str) -> anyhow::Result<Arc<dyn Reranker>> {
+    let mut rerankers = RERANKERS.lock().map_err(|_| anyhow!("Mutex poisoned"))?;
+    if let Some(reranker) = rerankers.get(name) {
+        return Ok(reranker.clone());
+    }
+
+    let reranker: Arc<dyn Reranker> = Arc::new(FastReranker::new(name, model_path)?);
+
+    rerankers.insert(name.to_string(), reranker.clone());
+    Ok(reranker)
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None, subcommand_required(false), subcommand = "stdio")]
@@ -53,11 +121,23 @@ struct Args {
     #[arg(short, long, env = "MODEL_PATH")]
     model_path: Option<PathBuf>,
 
+    /// Collection and tool registry (TOML), defaults to ~/.laravel_docs.toml when it exists
+    #[arg(long, env = "CONFIG_PATH")]
+    config: Option<PathBuf>,
+
+    /// Bearer tokens accepted by the sse and http servers, comma separated, `name:token` names the client
+    #[arg(long, env = "AUTH_TOKENS", hide_env_values = true)]
+    auth_tokens: Option<String>,
+
+    /// TOML file of `[[clients]]` with a name, a token and the collections each may read
+    #[arg(long, env = "AUTH_FILE")]
+    auth_file: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
-#[derive(Subcommand, Debug, Clone, Copy)]
+#[derive(Subcommand, Debug, Clone)]
 enum Commands {
     /// Run in stdio mode
     Stdio,
@@ -67,6 +147,61 @@ enum Commands {
         #[arg(short, long, default_value_t = 3000)]
         port: u16,
     },
+    /// Run in streamable HTTP mode, a single endpoint with Mcp-Session-Id sessions
+    Http {
+        /// Port for the HTTP server to bind to
+        #[arg(short, long, default_value_t = 3000)]
+        port: u16,
+        /// Path of the MCP endpoint
+        #[arg(long, default_value = "/mcp")]
+        path: String,
+    },
+    /// Chunk, embed and store a markdown docs directory into a collection
+    Ingest {
+        /// Collection (table) name, e.g. laravel_docs
+        #[arg(short, long)]
+        collection: String,
+        /// Docs directory to walk, defaults to DOCS_REPO_PATH
+        #[arg(short, long)]
+        source: Option<PathBuf>,
+        /// Maximum size of chunks in characters
+        #[arg(long, default_value_t = 400)]
+        chunk_size: usize,
+        /// Overlap between chunks in characters
+        #[arg(long, default_value_t = DEFAULT_CHUNK_OVERLAP)]
+        chunk_overlap: usize,
+        /// Splitter used to cut files into chunks: `markdown` or `recursive`
+        #[arg(long, default_value_t = SplitMode::Markdown)]
+        splitter: SplitMode,
+        /// Remove the docs version from the collection and re-embed every file, other versions are kept
+        #[arg(long)]
+        full: bool,
+        /// Tag the chunks with a docs version, e.g. 11.x, so several versions share one collection
+        #[arg(long)]
+        docs_version: Option<String>,
+    },
+    /// Fetch the configured git repositories and re-index the markdown files changed since the last sync
+    Sync {
+        /// Only sync the repositories of this collection
+        #[arg(short, long)]
+        collection: Option<String>,
+        /// Where the repositories are checked out, defaults to ~/.laravel_docs_repos
+        #[arg(long, env = "REPOS_PATH")]
+        workdir: Option<PathBuf>,
+    },
+    /// Inspect or upgrade the database schema
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum DbCommands {
+    /// Apply the pending schema migrations, the server and ingest also do it when they open the database
+    Migrate,
+    /// Show the schema version, the collections with their row counts and models, and the file size
+    Info,
 }
 
 #[tokio::main]
@@ -77,27 +212,259 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         std::env::var("HOME").unwrap()
     ));
     // let database_url = "/Users/fyyx/Documents/rust_projects/rust-mcp-demo/aa.db3".to_owned();
+    let config = Arc::new(load_config(args.config.as_deref())?);
+    let model_path = args.model_path.clone().unwrap_or_else(default_model_path);
     if let Some(command) = args.command {
         match command {
-            Commands::Stdio => start_stdio(&database_url).await?,
-            Commands::Sse { port } => start_sse(&database_url, port).await?,
+            Commands::Stdio => start_stdio(&database_url, config, &model_path).await?,
+            Commands::Sse { port } => {
+                let auth = load_auth(args.auth_tokens.as_deref(), args.auth_file.as_deref())?;
+                start_sse(&database_url, port, config, &model_path, auth).await?
+            }
+            Commands::Http { port, path } => {
+                let auth = load_auth(args.auth_tokens.as_deref(), args.auth_file.as_deref())?;
+                start_http(&database_url, port, &path, config, &model_path, auth).await?
+            }
+            Commands::Ingest {
+                collection,
+                source,
+                chunk_size,
+                chunk_overlap,
+                splitter,
+                full,
+                docs_version,
+            } => {
+                let source = source
+                    .or(args.docs_repo_path)
+                    .ok_or("missing docs directory, pass --source or set DOCS_REPO_PATH")?;
+                let chunker = TextChunker::new(source, chunk_size, chunk_overlap)
+                    .with_mode(splitter)
+                    .with_version(docs_version);
+                start_ingest(
+                    &database_url,
+                    &config,
+                    &model_path,
+                    &collection,
+                    chunker,
+                    full,
+                )?
+            }
+            Commands::Sync {
+                collection,
+                workdir,
+            } => {
+                let workdir = match workdir {
+                    Some(workdir) => workdir,
+                    None => std::env::var("HOME")
+                        .map(|home| Path::new(&home).join(".laravel_docs_repos"))
+                        .map_err(|_| anyhow!("HOME is not set, pass --workdir"))?,
+                };
+                start_sync(
+                    &database_url,
+                    &config,
+                    &model_path,
+                    collection.as_deref(),
+                    &workdir,
+                )?
+            }
+            Commands::Db { command } => start_db(&database_url, command)?,
         }
     } else {
-        start_stdio(&database_url).await?;
+        start_stdio(&database_url, config, &model_path).await?;
     }
     // start_sse(&database_url, 3000).await?;
 
     Ok(())
 }
 
-async fn start_stdio(database_url: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let service = LaravelDocs::new(database_url);
+/// Loads the registry from the given path, ~/.laravel_docs.toml, or the built-in default
+fn load_config(path: Option<&Path>) -> anyhow::Result<Config> {
+    let default_path =
+        std::env::var("HOME").map(|home| Path::new(&home).join(".laravel_docs.toml"));
+    let config = match (path, default_path) {
+        (Some(path), _) => Config::load(path)?,
+        (None, Ok(default_path)) if default_path.exists() => Config::load(&default_path)?,
+        (None, _) => Config::default(),
+    };
+
+    let builtin = LaravelDocs::tool_box().list();
+    if let Some(tool) = config
+        .tools
+        .iter()
+        .find(|t| builtin.iter().any(|b| b.name == t.name))
+    {
+        anyhow::bail!("tool `{}` clashes with a built-in tool", tool.name);
+    }
+    Ok(config)
+}
+
+/// Tokens of the sse and http servers, auth stays off when neither option is set
+fn load_auth(tokens: Option<&str>, file: Option<&Path>) -> anyhow::Result<Auth> {
+    let mut auth = Auth::new();
+    if let Some(tokens) = tokens {
+        auth = auth.with_tokens(tokens)?;
+    }
+    if let Some(file) = file {
+        auth = auth.with_file(file)?;
+    }
+    Ok(auth)
+}
+
+/// Puts the routes behind the bearer token check when tokens are configured
+fn protect(router: axum::Router, auth: Auth) -> axum::Router {
+    if !auth.is_enabled() {
+        tracing::warn!("no auth tokens configured, the server accepts unauthenticated requests");
+        return router;
+    }
+    router.layer(axum::middleware::from_fn_with_state(
+        Arc::new(auth),
+        require_bearer,
+    ))
+}
+
+async fn start_stdio(
+    database_url: &str,
+    config: Arc<Config>,
+    model_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service = LaravelDocs::new(database_url, config).with_model_path(model_path);
     service.serve(stdio()).await?.waiting().await?;
 
     Ok(())
 }
 
-async fn start_sse(database_url: &str, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+fn start_ingest(
+    database_url: &str,
+    config: &Config,
+    model_path: &Path,
+    collection: &str,
+    chunker: TextChunker,
+    full: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let source = chunker.input_dir();
+    if !source.is_dir() {
+        return Err(format!("docs directory not found: {}", source.display()).into());
+    }
+
+    let embedder = load_embedder(config, model_path, config.model_for(collection))?;
+    let vectorizer = Vectorizer::new(database_url, collection, embedder)?;
+    let summary = Ingestor::new(chunker, vectorizer)
+        .with_full_rebuild(full)
+        .run()?;
+    println!("{}", summary);
+    println!("  database   : {}", database_url);
+
+    Ok(())
+}
+
+fn start_sync(
+    database_url: &str,
+    config: &Config,
+    model_path: &Path,
+    collection: Option<&str>,
+    workdir: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let repos = config
+        .repos
+        .iter()
+        .filter(|r| collection.is_none_or(|c| c == r.collection))
+        .collect::<Vec<_>>();
+    if repos.is_empty() {
+        return Err("no repos configured, add [[repos]] to the config".into());
+    }
+
+    for repo in repos {
+        println!("Syncing {} into {}", repo.url, repo.collection);
+        let checkout = RepoCheckout::open(repo, workdir)?;
+        let commit = checkout.checkout()?;
+
+        let embedder = load_embedder(config, model_path, config.model_for(&repo.collection))?;
+        let vectorizer = Vectorizer::new(database_url, &repo.collection, embedder)?;
+        vectorizer.create_table()?;
+        let version = repo.version.as_deref();
+        let last = vectorizer.indexed_commit(version)?;
+        if last.as_deref() == Some(commit.to_string().as_str()) {
+            println!("  already at {}", commit);
+            continue;
+        }
+
+        let changed = match &last {
+            Some(last) => checkout.changed_files(last, commit)?,
+            None => None,
+        };
+        let chunker = TextChunker::new(checkout.docs_dir(), repo.chunk_size, repo.chunk_overlap)
+            .with_mode(SplitMode::Markdown)
+            .with_version(repo.version.clone());
+        let mut ingestor = Ingestor::new(chunker, vectorizer.clone());
+        // 没有记录过的提交, 或者旧提交已经不在仓库里时, 全量比对一次
+        let summary = match changed {
+            Some(files) => ingestor.run_files(files)?,
+            None => ingestor.run()?,
+        };
+        vectorizer.set_indexed_commit(version, &repo.url, &commit.to_string())?;
+
+        println!("{}", summary);
+        println!("  commit     : {}", commit);
+    }
+
+    Ok(())
+}
+
+fn start_db(database_url: &str, command: DbCommands) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        DbCommands::Migrate => {
+            let mut db = SqliteVector::open(database_url)?;
+            let applied = db.migrate()?;
+            for migration in &applied {
+                println!("Applied {:>3} {}", migration.version, migration.name);
+            }
+            if applied.is_empty() {
+                println!("Already up to date");
+            }
+            println!("  schema     : {}", db.schema_version()?);
+        }
+        DbCommands::Info => {
+            if !Path::new(database_url).exists() {
+                return Err(format!("database not found: {}", database_url).into());
+            }
+            let db = SqliteVector::open(database_url)?;
+            let size = std::fs::metadata(database_url)?.len();
+            println!("Database {}", database_url);
+            println!("  size       : {:.1} MiB", size as f64 / (1024.0 * 1024.0));
+            println!(
+                "  schema     : {} (latest {})",
+                db.schema_version()?,
+                laravel_docs_mcp::migrations::latest_version()
+            );
+            let pending = db.pending_migrations()?;
+            if !pending.is_empty() {
+                // 旧的表结构下不一定能查询集合
+                for migration in pending {
+                    println!("  pending    : {} {}", migration.version, migration.name);
+                }
+                println!("Run `db migrate` to upgrade the database");
+                return Ok(());
+            }
+            let collections = db.collections()?;
+            println!("  collections: {}", collections.len());
+            for collection in collections {
+                let rows = db
+                    .count(&collection)
+                    .map_or_else(|e| format!("error: {}", e), |n| format!("{} chunks", n));
+                let model = match db.collection_model(&collection)? {
+                    Some((model, dimension)) => format!("{} ({} dimensions)", model, dimension),
+                    None => "unknown model".to_string(),
+                };
+                println!("    {:<24} {:>14}  {}", collection, rows, model);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Logs to a file next to the database
+fn init_logging(database_url: &str) -> Result<(), Box<dyn std::error::Error>> {
     let mut data_path: PathBuf = database_url.into();
     let log_path = format!("{}/mcp_service.log", {
         data_path.pop();
@@ -109,42 +476,220 @@ async fn start_sse(database_url: &str, port: u16) -> Result<(), Box<dyn std::err
         .append(true)
         .open(log_path)?;
 
-    let _ = MODEL.clone();
-    println!("model load");
-
     // Set up the file logger
     log::set_boxed_logger(Box::new(FileLogger {
         file: std::sync::Mutex::new(log_file),
     }))?;
     log::set_max_level(log::LevelFilter::Debug);
+    Ok(())
+}
 
-    tracing::info!("Starting Postgres MCP server in SSE mode on port {}", port);
+/// Loads the models used by the configured collections
+fn preload_models(config: &Config, model_path: &Path) -> anyhow::Result<()> {
+    // 与检索时一样按集合解析模型, 集合自己配置的模型优先于工具的
+    let models = config
+        .searched_collections()
+        .into_iter()
+        .chain(config.collections.keys().map(|c| c.as_str()))
+        .map(|c| config.model_for(c))
+        .collect::<std::collections::BTreeSet<_>>();
+    for model in models {
+        load_embedder(config, model_path, model)?;
+    }
+    for settings in config.collections.values().filter(|c| c.rerank) {
+        load_reranker(
+            model_path,
+            settings.reranker.as_deref().unwrap_or(DEFAULT_RERANKER),
+        )?;
+    }
+    println!("model load");
+    Ok(())
+}
 
-    let addr = format!("0.0.0.0:{}", port);
-    // Store bind address and cancellation token separately
-    let bind_addr: std::net::SocketAddr = addr.parse()?;
-    let ct_main = tokio_util::sync::CancellationToken::new();
-
-    let config = SseServerConfig {
-        bind: bind_addr, // Use stored address
-        sse_path: "/sse".to_string(),
-        post_path: "/message".to_string(),
-        // Clone the token for the config
-        ct: ct_main.clone(),
+/// Ready once the database opens and holds every collection the tools search
+fn check_ready(database_url: &str, config: &Config) -> Result<(), String> {
+    let db = SqliteVector::new(database_url).map_err(|e| format!("database: {}", e))?;
+    let collections = db.collections().map_err(|e| format!("database: {}", e))?;
+    let missing = config
+        .searched_collections()
+        .into_iter()
+        .filter(|c| !collections.iter().any(|name| name == *c))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(format!("missing collections: {}", missing.join(", ")));
+    }
+    Ok(())
+}
+
+/// Resolves on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
     };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    let sse_server = SseServer::serve_with_config(config).await?;
-    let db_path_owned = database_url.to_string();
+    tokio::select! {
+        _ = ctrl_c => log::info!("Ctrl-C received, shutting down..."),
+        _ = terminate => log::info!("SIGTERM received, shutting down..."),
+    }
+}
+
+/// Serves the MCP routes next to /healthz and /readyz. The port is bound before
+/// the models load so probes can tell a cold start from a dead process, and on
+/// shutdown the running tool calls are drained before the sessions are closed
+async fn serve(
+    listener: tokio::net::TcpListener,
+    router: axum::Router,
+    ct: tokio_util::sync::CancellationToken,
+    health: Arc<Health>,
+    database_url: &str,
+    config: Arc<Config>,
+    model_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db_path = database_url.to_string();
+    let ready_config = config.clone();
+    let router = router.merge(health::router(health.clone(), move || {
+        check_ready(&db_path, &ready_config)
+    }));
 
-    let service_ct = sse_server.with_service(move || LaravelDocs::new(&db_path_owned));
+    let loading = health.clone();
+    let failed = ct.clone();
+    // 模型加载失败时停止服务, 错误交给调用方以非零状态退出
+    let failure = Arc::new(std::sync::Mutex::new(None));
+    let preload_failure = failure.clone();
+    let model_path = model_path.to_path_buf();
+    tokio::task::spawn_blocking(move || match preload_models(&config, &model_path) {
+        Ok(()) => {
+            loading.set_ready();
+            log::info!("models loaded, server is ready");
+        }
+        Err(e) => {
+            log::error!("failed to load models: {}", e);
+            if let Ok(mut failure) = preload_failure.lock() {
+                *failure = Some(e);
+            }
+            failed.cancel();
+        }
+    });
 
-    tokio::signal::ctrl_c().await?;
-    tracing::info!("Ctrl-C received, shutting down...");
-    service_ct.cancel();
-    ct_main.cancel();
+    let shutdown = ct.clone();
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            tokio::select! {
+                _ = shutdown_signal() => {}
+                _ = shutdown.cancelled() => return,
+            }
+            log::info!("draining {} running tool calls", health.in_flight());
+            if !health.drain(DRAIN_TIMEOUT).await {
+                log::warn!(
+                    "{} tool calls still running after {:?}, closing anyway",
+                    health.in_flight(),
+                    DRAIN_TIMEOUT
+                );
+            }
+            shutdown.cancel();
+        })
+        .await?;
+    if let Some(e) = failure.lock().ok().and_then(|mut f| f.take()) {
+        return Err(e.context("failed to load models").into());
+    }
     Ok(())
 }
 
+async fn start_sse(
+    database_url: &str,
+    port: u16,
+    config: Arc<Config>,
+    model_path: &Path,
+    auth: Auth,
+) -> Result<(), Box<dyn std::error::Error>> {
+    init_logging(database_url)?;
+
+    tracing::info!("Starting Postgres MCP server in SSE mode on port {}", port);
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    let ct = tokio_util::sync::CancellationToken::new();
+    let health = Arc::new(Health::new());
+
+    let server = SseServer::new("/sse", "/message", ct.clone());
+    // 指标里有集合名, 和 MCP 端点一样需要令牌, 探针用的 healthz/readyz 保持公开
+    let router = protect(server.router().merge(metrics::router()), auth);
+    let db_path_owned = database_url.to_string();
+    let service_config = config.clone();
+    let service_health = health.clone();
+    let service_model_path = model_path.to_path_buf();
+    server.with_service(move |client| {
+        LaravelDocs::new(&db_path_owned, service_config.clone())
+            .with_model_path(&service_model_path)
+            .with_client(client)
+            .with_health(service_health.clone())
+    });
+
+    serve(
+        listener,
+        router,
+        ct,
+        health,
+        database_url,
+        config,
+        model_path,
+    )
+    .await
+}
+
+async fn start_http(
+    database_url: &str,
+    port: u16,
+    path: &str,
+    config: Arc<Config>,
+    model_path: &Path,
+    auth: Auth,
+) -> Result<(), Box<dyn std::error::Error>> {
+    init_logging(database_url)?;
+
+    tracing::info!(
+        "Starting MCP server in streamable HTTP mode on port {}",
+        port
+    );
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    let ct = tokio_util::sync::CancellationToken::new();
+    let health = Arc::new(Health::new());
+
+    let server = StreamableHttpServer::new(path, ct.clone());
+    // 指标里有集合名, 和 MCP 端点一样需要令牌, 探针用的 healthz/readyz 保持公开
+    let router = protect(server.router().merge(metrics::router()), auth);
+    let db_path_owned = database_url.to_string();
+    let service_config = config.clone();
+    let service_health = health.clone();
+    let service_model_path = model_path.to_path_buf();
+    server.with_service(move |client| {
+        LaravelDocs::new(&db_path_owned, service_config.clone())
+            .with_model_path(&service_model_path)
+            .with_client(client)
+            .with_health(service_health.clone())
+    });
+
+    serve(
+        listener,
+        router,
+        ct,
+        health,
+        database_url,
+        config,
+        model_path,
+    )
+    .await
+}
+
 // Custom file logger implementation
 struct FileLogger {
     file: std::sync::Mutex<std::fs::File>,
@@ -208,23 +753,203 @@ impl log::Log for FileLogger {
 #[derive(Clone)]
 pub struct LaravelDocs {
     db_path: String,
+    config: Arc<Config>,
     vectorizers: Arc<RwLock<HashMap<String, Arc<Vectorizer>>>>,
+    /// Connection the tools read the catalog, pages and query log through,
+    /// opened on first use
+    db: Arc<OnceLock<Mutex<SqliteVector>>>,
+    /// Directory the embedding and reranker models are cached in
+    model_path: PathBuf,
+    /// Authenticated caller of the session, `None` when auth is off
+    client: Option<Arc<Client>>,
+    /// Counts the running tool calls so shutdown can wait for them
+    health: Arc<Health>,
+}
+
+/// Parameters of the tools declared in the config
+#[derive(Deserialize, JsonSchema)]
+pub struct DocsToolParams {
+    /// What to look for in the documentation
+    pub query: String,
+    /// Maximum number of hits, defaults to the tool's default_limit
+    pub limit: Option<usize>,
+    /// Ranking to use: vector (default), keyword or hybrid
+    pub mode: Option<String>,
+    /// Rerank the hits with a cross-encoder, defaults to the collection setting
+    pub rerank: Option<bool>,
+    #[serde(flatten)]
+    pub filters: FilterParams,
+}
+
+/// Filters a search call can restrict the hits with
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct FilterParams {
+    /// Docs version to search, e.g. 10.x, defaults to the collection's current
+    /// version. See list_versions
+    pub version: Option<String>,
+    /// Only pages whose path starts with this, e.g. `eloquent` for all Eloquent pages
+    pub source_prefix: Option<String>,
+    /// Only chunks whose heading path contains this, e.g. `Eager Loading`
+    pub section: Option<String>,
+    /// Only pages of a tag listed by list_collections, e.g. `testing`. Combined
+    /// with source_prefix the pages of both are searched
+    pub tag: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct LaravelResult {
-    pub documents: Vec<String>,
+    /// Id of the search in the query log, pass it to report_feedback
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_id: Option<i64>,
+    pub documents: Vec<Document>,
+}
+
+#[derive(Serialize)]
+pub struct CollectionsResult {
+    pub collections: Vec<CollectionSummary>,
+}
+
+#[derive(Serialize)]
+pub struct VersionsResult {
+    pub collections: Vec<CollectionVersions>,
+}
+
+#[derive(Serialize)]
+pub struct CollectionVersions {
+    pub name: String,
+    /// Version searched when the caller does not pass one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_version: Option<String>,
+    pub versions: Vec<VersionSummary>,
+}
+
+#[derive(Serialize)]
+pub struct VersionSummary {
+    /// `null` for chunks ingested without a version
+    pub version: Option<String>,
+    pub chunks: usize,
+}
+
+#[derive(Serialize)]
+pub struct CollectionSummary {
+    pub name: String,
+    pub chunks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Tags search_docs can restrict the hits to
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ChunkContext {
+    /// Ids of the stitched chunks, in file order
+    pub ids: Vec<String>,
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Heading path of the requested chunk
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub section: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Chunks joined with their overlaps removed
+    pub text: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DocPage {
+    pub source: String,
+    /// Path relative to the docs directory, usable as a docs:// resource path
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Full markdown of the page
+    pub text: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Document {
+    /// Chunk id, `{md5(source)}-{index}`
+    pub id: String,
+    /// Source file path the chunk was ingested from
+    pub source: String,
+    /// Public page of the chunk, when the collection has a known doc site
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Heading path of the chunk, empty for collections ingested before breadcrumbs existed
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub section: String,
+    /// Anchor slug of the nearest heading, also kept for collections without a url_template
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<String>,
+    /// Docs version the chunk belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// L2 distance from the query, lower is closer, absent for keyword only hits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<f64>,
+    /// Relevance, higher is better: cosine similarity in vector mode,
+    /// negated BM25 in keyword mode, fused rank score in hybrid mode,
+    /// cross-encoder score when reranked
+    pub score: f64,
+    pub text: String,
 }
 
 #[tool(tool_box)]
 impl LaravelDocs {
-    pub fn new(db_path: &str) -> Self {
+    pub fn new(db_path: &str, config: Arc<Config>) -> Self {
         Self {
             db_path: db_path.to_string(),
+            config,
             vectorizers: Arc::new(RwLock::new(HashMap::new())),
+            db: Default::default(),
+            model_path: default_model_path(),
+            client: None,
+            health: Arc::new(Health::new()),
+        }
+    }
+
+    /// Loads the models from this directory instead of the default cache
+    pub fn with_model_path(mut self, model_path: &Path) -> Self {
+        self.model_path = model_path.to_path_buf();
+        self
+    }
+
+    /// Shares the server's health so shutdown waits for this session's tool calls
+    pub fn with_health(mut self, health: Arc<Health>) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Restricts the session to the collections the client may read
+    pub fn with_client(mut self, client: Option<Arc<Client>>) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn allows(&self, collection: &str) -> bool {
+        self.client.as_ref().is_none_or(|c| c.allows(collection))
+    }
+
+    /// Fails with Forbidden when the client may not read the collection
+    fn check_access(&self, collection: &str) -> Result<(), AppError> {
+        match &self.client {
+            Some(client) if !client.allows(collection) => Err(AppError::Forbidden(format!(
+                "client `{}` may not read collection `{}`",
+                client.name, collection
+            ))),
+            _ => Ok(()),
         }
     }
 
+    /// Name of the client shown in the logs
+    fn client_name(&self) -> &str {
+        self.client.as_ref().map_or("-", |c| c.name.as_str())
+    }
+
     async fn get_vectorizer(&self, collection: &str) -> anyhow::Result<Arc<Vectorizer>> {
         {
             let vectorizers = self.vectorizers.read().await;
@@ -234,7 +959,12 @@ impl LaravelDocs {
         }
 
         //  TODO 这里还有并发漏洞之后处理
-        let v = match Vectorizer::new(&self.db_path, collection, 384, MODEL.clone()) {
+        let embedder = load_embedder(
+            &self.config,
+            &self.model_path,
+            self.config.model_for(collection),
+        )?;
+        let v = match Vectorizer::new(&self.db_path, collection, embedder) {
             Ok(v) => Arc::new(v),
             Err(e) => {
                 println!("{:?}", e);
@@ -250,119 +980,442 @@ impl LaravelDocs {
         Ok(entry.clone())
     }
 
-    #[tool(
-        name = "get_laravel_context",
-        description = "有关laravel框架的问题 都先调用 get_laravel_context 这里的文档是最新的"
-    )]
-    async fn get_laravel_context(&self, #[tool(param)] query: String) -> AppResultWrapper {
-        log::info!("Received query: {}", query);
-        let vector = match self.get_vectorizer("laravel_docs").await {
-            Ok(v) => v,
-            Err(e) => {
-                println!("{:?}", e);
-                return AppResultWrapper(Err(e.into()));
+    /// The shared connection, migrations run once when it is opened
+    fn db(&self) -> anyhow::Result<MutexGuard<'_, SqliteVector>> {
+        let db = match self.db.get() {
+            Some(db) => db,
+            None => {
+                // 并发打开时只保留先写入的连接
+                let _ = self.db.set(Mutex::new(SqliteVector::new(&self.db_path)?));
+                self.db
+                    .get()
+                    .ok_or_else(|| anyhow!("Failed to open the database"))?
             }
         };
-        let results = match vector.search(&query, Some(20)) {
-            Ok(r) => r,
-            Err(e) => {
-                println!("{:?}", e);
-                return AppResultWrapper(Err(e.into()));
+        db.lock().map_err(|_| anyhow!("Mutex poisoned"))
+    }
+
+    /// Names and row counts of the vec0 collections that exist in the database
+    /// and that the client may read, counts every collection so only
+    /// `list_collections` uses it
+    fn collections(&self) -> anyhow::Result<Vec<(CollectionName, usize)>> {
+        let db = self.db()?;
+        db.collections()?
+            .into_iter()
+            .filter(|name| self.allows(name.as_str()))
+            .map(|name| {
+                let count = db.count(&name)?;
+                Ok((name, count))
+            })
+            .collect()
+    }
+
+    /// Validates a collection passed by the client, fails with NotFound when
+    /// it does not exist in the database
+    fn ensure_collection(&self, collection: &str) -> Result<CollectionName, AppError> {
+        let name = parse_collection(collection)?;
+        self.check_access(collection)?;
+        if self.db()?.has_collection(&name)? {
+            return Ok(name);
+        }
+        Err(AppError::NotFound(format!(
+            "collection `{}` does not exist, call list_collections to see the available ones",
+            collection
+        )))
+    }
+
+    /// Chunk `id` with `before` chunks before and `after` chunks after it, stitched together
+    fn surrounding_chunks(
+        &self,
+        collection: &str,
+        id: &str,
+        before: usize,
+        after: usize,
+    ) -> Result<ChunkContext, AppError> {
+        let name = self.ensure_collection(collection)?;
+        let ids = neighbour_ids(id, before, after).ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "invalid chunk id `{}`, expected the id of a search hit",
+                id
+            ))
+        })?;
+        let db = self.db()?;
+        let chunks = db
+            .chunks_by_ids(&name, &ids)?
+            .into_iter()
+            .filter_map(StoredChunk::into_text_chunk)
+            .collect::<Vec<_>>();
+        let chunk = chunks
+            .iter()
+            .find(|c| c.id == id)
+            .ok_or_else(|| AppError::NotFound(format!("chunk `{}` does not exist", id)))?;
+
+        let settings = self.config.collection(collection);
+        Ok(ChunkContext {
+            ids: chunks.iter().map(|c| c.id.clone()).collect(),
+            url: doc_url(
+                settings.url_template.as_deref(),
+                &chunk.source,
+                chunk.anchor.as_deref(),
+            ),
+            source: chunk.source.clone(),
+            section: chunk.section.clone(),
+            version: chunk.version.clone(),
+            text: stitch_chunks(
+                &chunks.iter().map(|c| c.text.as_str()).collect::<Vec<_>>(),
+                db.chunk_overlap(&name, chunk.version.as_deref())?,
+            ),
+        })
+    }
+
+    /// Page ingested from `source`, which is either the ingested file path or
+    /// the path relative to the docs directory
+    fn doc_page(
+        &self,
+        collection: &str,
+        source: &str,
+        version: Option<&str>,
+    ) -> Result<DocPage, AppError> {
+        let name = self.ensure_collection(collection)?;
+        let settings = self.config.collection(collection);
+        let db = self.db()?;
+        let mut pages = db
+            .pages(&name)?
+            .into_iter()
+            .filter(|p| p.source == source || p.path == source)
+            .filter(|p| version.is_none() || p.version.as_deref() == version)
+            .collect::<Vec<_>>();
+        // 没有指定版本时优先返回集合的当前版本
+        pages.sort_by_key(|p| p.version != settings.current_version);
+        let page = pages.into_iter().next().ok_or_else(|| {
+            AppError::NotFound(format!(
+                "page `{}` does not exist in `{}`",
+                source, collection
+            ))
+        })?;
+
+        let text = db
+            .page_content(&name, page.version.as_deref(), &page.source)?
+            .ok_or_else(|| AppError::NotFound(format!("page `{}` has no content", source)))?;
+        Ok(DocPage {
+            url: doc_url(settings.url_template.as_deref(), &page.source, None),
+            source: page.source,
+            path: page.path,
+            version: page.version,
+            text,
+        })
+    }
+
+    /// Doc pages of every collection as MCP resources
+    fn resources(&self) -> anyhow::Result<Vec<rmcp::model::Resource>> {
+        let db = self.db()?;
+        let mut resources = Vec::new();
+        for collection in db.collections()? {
+            if !self.allows(collection.as_str()) {
+                continue;
+            }
+            for page in db.pages(&collection)? {
+                let mut resource = RawResource::new(
+                    page_uri(collection.as_str(), page.version.as_deref(), &page.path),
+                    page.path.clone(),
+                );
+                resource.description = Some(match &page.version {
+                    Some(version) => format!("{} {} page {}", collection, version, page.path),
+                    None => format!("{} page {}", collection, page.path),
+                });
+                resource.mime_type = Some("text/markdown".to_string());
+                resource.size = page.size.map(|s| s as u32);
+                resources.push(resource.no_annotation());
             }
-        };
-        let docs = parse_docs(results);
-        if docs.is_empty() {
-            return AppResultWrapper(Ok(CallToolResult::success(vec![Content::text(format!(
-                "No relevant {} documentation found for the query.",
-                "laravel_docs"
-            ))])));
         }
-        let content = match Content::json(&LaravelResult { documents: docs }) {
-            Ok(c) => c,
-            Err(e) => return AppResultWrapper(Err(AppError::InternalServerError(e.to_string()))),
-        };
-        AppResultWrapper(Ok(CallToolResult::success(vec![content])))
+        Ok(resources)
     }
 
-    #[tool(
-        name = "get_laravel_livewire_context",
-        description = "有关laravel livewire 框架的问题 都先调用 get_laravel_livewire_context 这里的文档是最新的"
-    )]
-    async fn get_laravel_livewire_context(&self, #[tool(param)] query: String) -> AppResultWrapper {
-        log::info!("Received query: {}", query);
-        let vector = match self.get_vectorizer("laravel_livewire_docs").await {
-            Ok(v) => v,
-            Err(e) => return AppResultWrapper(Err(e.into())),
+    /// Markdown of the page a `docs://` uri points to
+    fn read_page(&self, uri: &str) -> Result<String, AppError> {
+        let (collection, version, path) = parse_page_uri(uri).ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "invalid resource uri `{}`, expected docs://{{collection}}/{{path}}",
+                uri
+            ))
+        })?;
+        let collection = parse_collection(&collection)?;
+        self.check_access(collection.as_str())?;
+        let db = self.db()?;
+        let not_found = || AppError::NotFound(format!("resource `{}` does not exist", uri));
+        if !db.has_collection(&collection)? {
+            return Err(not_found());
+        }
+        let page = db
+            .pages(&collection)?
+            .into_iter()
+            .find(|p| p.path == path && p.version == version)
+            .ok_or_else(not_found)?;
+        db.page_content(&collection, version.as_deref(), &page.source)?
+            .ok_or_else(not_found)
+    }
+
+    /// Runs a search on a collection and returns the parsed hits, logged in `query_log`
+    async fn search_documents(
+        &self,
+        collection: &str,
+        query: &str,
+        limit: usize,
+        mode: Option<&str>,
+        rerank: Option<bool>,
+        filters: &FilterParams,
+    ) -> Result<LaravelResult, AppError> {
+        self.check_access(collection)?;
+        let started = std::time::Instant::now();
+        let mode = match mode {
+            Some(mode) => mode
+                .parse::<SearchMode>()
+                .map_err(|e| AppError::BadRequest(e.to_string()))?,
+            None => SearchMode::default(),
         };
-        let results = match vector.search(&query, Some(20)) {
-            Ok(r) => r,
-            Err(e) => return AppResultWrapper(Err(e.into())),
+        let settings = self.config.collection(collection);
+        let rerank = rerank.unwrap_or(settings.rerank);
+        let filter = search_filter(&settings, filters)?;
+        log::info!(
+            "Received query: {} ({}, {}, rerank {}, version {:?}, client {})",
+            query,
+            collection,
+            mode,
+            rerank,
+            filter.version,
+            self.client_name()
+        );
+        let vector = self.get_vectorizer(collection).await.inspect_err(|e| {
+            println!("{:?}", e);
+        })?;
+        // 嵌入可能是阻塞的 HTTP 请求, 重排也要跑模型, 都放到阻塞线程上, 不占住运行时的工作线程
+        let searcher = vector.clone();
+        let search_query = query.to_string();
+        let search_filter = filter.clone();
+        let model_path = self.model_path.clone();
+        let reranker = settings
+            .reranker
+            .clone()
+            .unwrap_or_else(|| DEFAULT_RERANKER.to_string());
+        let candidates = settings.rerank_candidates.unwrap_or(RERANK_CANDIDATES);
+        let results = tokio::task::spawn_blocking(move || {
+            if rerank {
+                let reranker = load_reranker(&model_path, &reranker)?;
+                searcher.search_reranked(
+                    &search_query,
+                    limit,
+                    mode,
+                    &search_filter,
+                    reranker.as_ref(),
+                    candidates,
+                )
+            } else {
+                searcher.search_with_mode(&search_query, Some(limit), mode, &search_filter)
+            }
+        })
+        .await
+        .map_err(|e| anyhow!("search task failed: {}", e))?
+        .inspect_err(|e| {
+            println!("{:?}", e);
+        })?;
+        let docs = parse_docs(settings.url_template.as_deref(), results);
+        metrics::global().search_results(collection, docs.len());
+
+        let entry = QueryLogEntry {
+            collection: collection.to_string(),
+            query: query.to_string(),
+            mode: if rerank {
+                format!("{}+rerank", mode)
+            } else {
+                mode.to_string()
+            },
+            version: filter.version.clone(),
+            client: self.client.as_ref().map(|c| c.name.clone()),
+            hits: docs
+                .iter()
+                .map(|doc| LoggedHit {
+                    id: doc.id.clone(),
+                    score: doc.score,
+                })
+                .collect(),
+            latency_ms: started.elapsed().as_secs_f64() * 1000.0,
         };
-        let docs = parse_docs(results);
-        if docs.is_empty() {
-            return AppResultWrapper(Ok(CallToolResult::success(vec![Content::text(format!(
+        // 查询日志写失败不影响检索结果
+        let query_id = vector
+            .log_query(&entry)
+            .inspect_err(|e| log::warn!("Failed to log query: {}", e))
+            .ok();
+        Ok(LaravelResult {
+            query_id,
+            documents: docs,
+        })
+    }
+
+    /// Marks hits of a logged search as helpful, returns how many were new
+    fn report_helpful(&self, query_id: i64, chunk_ids: &[String]) -> Result<usize, AppError> {
+        let db = self.db()?;
+        let entry = db
+            .logged_query(query_id)?
+            .ok_or_else(|| AppError::NotFound(format!("query `{}` does not exist", query_id)))?;
+        self.check_access(&entry.collection)?;
+        // 只有发起查询的客户端能反馈它的结果
+        if entry.client.as_deref() != self.client.as_ref().map(|c| c.name.as_str()) {
+            return Err(AppError::Forbidden(format!(
+                "query `{}` was made by another client",
+                query_id
+            )));
+        }
+        let unknown = chunk_ids
+            .iter()
+            .filter(|id| !entry.hits.iter().any(|hit| &hit.id == *id))
+            .map(|id| id.as_str())
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            return Err(AppError::BadRequest(format!(
+                "chunks {} were not returned by query `{}`",
+                unknown.join(", "),
+                query_id
+            )));
+        }
+        Ok(db.record_feedback(query_id, chunk_ids)?)
+    }
+
+    async fn search_collection(
+        &self,
+        collection: &str,
+        query: &str,
+        limit: usize,
+        mode: Option<&str>,
+        rerank: Option<bool>,
+        filters: &FilterParams,
+    ) -> Result<CallToolResult, AppError> {
+        let result = self
+            .search_documents(collection, query, limit, mode, rerank, filters)
+            .await?;
+        if result.documents.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
                 "No relevant {} documentation found for the query.",
-                "laravel_livewire_docs"
-            ))])));
+                collection
+            ))]));
         }
-        let content = match Content::json(&LaravelResult { documents: docs }) {
-            Ok(c) => c,
-            Err(e) => return AppResultWrapper(Err(AppError::InternalServerError(e.to_string()))),
-        };
-        AppResultWrapper(Ok(CallToolResult::success(vec![content])))
+        let content =
+            Content::json(&result).map_err(|e| AppError::InternalServerError(e.to_string()))?;
+        Ok(CallToolResult::success(vec![content]))
     }
 
+    /// Fills a prompt with the hits of its searches
+    async fn render_prompt(
+        &self,
+        prompt: &LaravelPrompt,
+        collection: &str,
+    ) -> Result<String, AppError> {
+        self.ensure_collection(collection)?;
+        let mut results = Vec::new();
+        for search in prompt.searches() {
+            let result = self
+                .search_documents(
+                    collection,
+                    &search.query,
+                    PROMPT_HITS,
+                    None,
+                    None,
+                    &FilterParams {
+                        version: search.version.clone(),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            results.push(
+                result
+                    .documents
+                    .into_iter()
+                    .map(|doc| Citation {
+                        title: if doc.section.is_empty() {
+                            doc.source
+                        } else {
+                            doc.section
+                        },
+                        url: doc.url,
+                        text: doc.text,
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+        Ok(prompt.render(&results))
+    }
 
     #[tool(
-        name = "get_pingora_context",
-        description = "有关pingora 框架的问题 都先调用 get_pingora_context 这里的文档是最新的"
+        name = "search_docs",
+        description = "Search any indexed documentation collection. Call list_collections first to see which collections exist."
     )]
-    async fn get_pingora_context(&self, #[tool(param)] query: String) -> AppResultWrapper {
-        log::info!("Received query: {}", query);
-        let vector = match self.get_vectorizer("pingora_docs").await {
-            Ok(v) => v,
-            Err(e) => return AppResultWrapper(Err(e.into())),
-        };
-        let results = match vector.search(&query, Some(20)) {
-            Ok(r) => r,
-            Err(e) => return AppResultWrapper(Err(e.into())),
-        };
-        let docs = parse_docs(results);
-        if docs.is_empty() {
-            return AppResultWrapper(Ok(CallToolResult::success(vec![Content::text(format!(
-                "No relevant {} documentation found for the query.",
-                "pingora_docs"
-            ))])));
+    async fn search_docs(
+        &self,
+        #[tool(param)]
+        #[schemars(description = "Collection name as returned by list_collections")]
+        collection: String,
+        #[tool(param)]
+        #[schemars(description = "What to look for in the documentation")]
+        query: String,
+        #[tool(param)]
+        #[schemars(
+            description = "Maximum number of hits, defaults to the collection's default_limit"
+        )]
+        limit: Option<usize>,
+        #[tool(param)]
+        #[schemars(
+            description = "Ranking: `vector` (semantic, default), `keyword` (exact API names like whereBelongsTo) or `hybrid` (both)"
+        )]
+        mode: Option<String>,
+        #[tool(param)]
+        #[schemars(
+            description = "Rerank the hits with a cross-encoder, slower but more precise. Defaults to the collection setting"
+        )]
+        rerank: Option<bool>,
+        #[tool(param)]
+        #[serde(flatten)]
+        filters: FilterParams,
+    ) -> AppResultWrapper {
+        if let Err(e) = self.ensure_collection(&collection) {
+            return AppResultWrapper(Err(e));
         }
-        let content = match Content::json(&LaravelResult { documents: docs }) {
-            Ok(c) => c,
-            Err(e) => return AppResultWrapper(Err(AppError::InternalServerError(e.to_string()))),
-        };
-        AppResultWrapper(Ok(CallToolResult::success(vec![content])))
+        let limit = limit
+            .unwrap_or_else(|| self.config.limit_for(&collection))
+            .clamp(1, MAX_LIMIT);
+        AppResultWrapper(
+            self.search_collection(
+                &collection,
+                &query,
+                limit,
+                mode.as_deref(),
+                rerank,
+                &filters,
+            )
+            .await,
+        )
     }
 
     #[tool(
-        name = "get_phper_context",
-        description = "有关phper 框架的问题 都先调用 get_phper_context 这里的文档是最新的"
+        name = "list_collections",
+        description = "List the documentation collections that can be passed to search_docs, with chunk counts, descriptions and the tags search_docs can filter on"
     )]
-    async fn get_phper_context(&self, #[tool(param)] query: String) -> AppResultWrapper {
-        log::info!("Received query: {}", query);
-        let vector = match self.get_vectorizer("phper_docs").await {
-            Ok(v) => v,
-            Err(e) => return AppResultWrapper(Err(e.into())),
-        };
-        let results = match vector.search(&query, Some(20)) {
-            Ok(r) => r,
+    async fn list_collections(&self) -> AppResultWrapper {
+        let collections = match self.collections() {
+            Ok(c) => c,
             Err(e) => return AppResultWrapper(Err(e.into())),
         };
-        let docs = parse_docs(results);
-        if docs.is_empty() {
-            return AppResultWrapper(Ok(CallToolResult::success(vec![Content::text(format!(
-                "No relevant {} documentation found for the query.",
-                "phper_docs"
-            ))])));
-        }
-        let content = match Content::json(&LaravelResult { documents: docs }) {
+        let collections = collections
+            .into_iter()
+            .map(|(name, chunks)| {
+                let settings = self.config.collection(name.as_str());
+                CollectionSummary {
+                    description: settings.description,
+                    tags: settings.tags.into_keys().collect(),
+                    name: name.to_string(),
+                    chunks,
+                }
+            })
+            .collect();
+        let content = match Content::json(&CollectionsResult { collections }) {
             Ok(c) => c,
             Err(e) => return AppResultWrapper(Err(AppError::InternalServerError(e.to_string()))),
         };
@@ -370,55 +1423,405 @@ impl LaravelDocs {
     }
 
     #[tool(
-        name = "get_laravel_comments_context",
-        description = "有关laravel_comments 库的问题 都先调用 get_laravel_comments_context 这里的文档是最新的"
+        name = "list_versions",
+        description = "List the docs versions indexed in each collection and which one is searched by default"
     )]
-    async fn get_laravel_comments_context(&self, #[tool(param)] query: String) -> AppResultWrapper {
-        log::info!("Received query: {}", query);
-        let vector = match self.get_vectorizer("laravel_comments_docs").await {
-            Ok(v) => v,
-            Err(e) => return AppResultWrapper(Err(e.into())),
-        };
-        let results = match vector.search(&query, Some(20)) {
+    async fn list_versions(
+        &self,
+        #[tool(param)]
+        #[schemars(description = "Only show this collection, defaults to all of them")]
+        collection: Option<String>,
+    ) -> AppResultWrapper {
+        if let Some(name) = &collection
+            && let Err(e) = parse_collection(name).and_then(|_| self.check_access(name))
+        {
+            return AppResultWrapper(Err(e));
+        }
+        let result = (|| -> anyhow::Result<VersionsResult> {
+            let db = self.db()?;
+            let collections = db
+                .collections()?
+                .into_iter()
+                .filter(|name| self.allows(name.as_str()))
+                .filter(|name| collection.as_ref().is_none_or(|c| name == c.as_str()))
+                .map(|name| {
+                    let versions = db
+                        .versions(&name)?
+                        .into_iter()
+                        .map(|(version, chunks)| VersionSummary { version, chunks })
+                        .collect();
+                    Ok(CollectionVersions {
+                        current_version: self.config.collection(name.as_str()).current_version,
+                        name: name.to_string(),
+                        versions,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(VersionsResult { collections })
+        })();
+
+        let result = match result {
             Ok(r) => r,
             Err(e) => return AppResultWrapper(Err(e.into())),
         };
-        let docs = parse_docs(results);
-        if docs.is_empty() {
-            return AppResultWrapper(Ok(CallToolResult::success(vec![Content::text(format!(
-                "No relevant {} documentation found for the query.",
-                "laravel_comments_docs"
-            ))])));
+        if let Some(name) = &collection
+            && result.collections.is_empty()
+        {
+            return AppResultWrapper(Err(AppError::NotFound(format!(
+                "collection `{}` does not exist, call list_collections to see the available ones",
+                name
+            ))));
         }
-        let content = match Content::json(&LaravelResult { documents: docs }) {
+        let content = match Content::json(&result) {
             Ok(c) => c,
             Err(e) => return AppResultWrapper(Err(AppError::InternalServerError(e.to_string()))),
         };
         AppResultWrapper(Ok(CallToolResult::success(vec![content])))
     }
+
+    #[tool(
+        name = "get_surrounding_chunks",
+        description = "Expand a search hit with the chunks right before and after it in the same page, stitched into one text. Use it when a hit is cut off mid answer"
+    )]
+    async fn get_surrounding_chunks(
+        &self,
+        #[tool(param)]
+        #[schemars(description = "Collection the hit came from")]
+        collection: String,
+        #[tool(param)]
+        #[schemars(description = "Chunk id of a search hit")]
+        id: String,
+        #[tool(param)]
+        #[schemars(description = "Chunks to include before the hit, defaults to 1, at most 10")]
+        before: Option<usize>,
+        #[tool(param)]
+        #[schemars(description = "Chunks to include after the hit, defaults to 1, at most 10")]
+        after: Option<usize>,
+    ) -> AppResultWrapper {
+        let result = self.surrounding_chunks(
+            &collection,
+            &id,
+            before.unwrap_or(1).min(MAX_SURROUNDING),
+            after.unwrap_or(1).min(MAX_SURROUNDING),
+        );
+        AppResultWrapper(result.and_then(|context| {
+            let content = Content::json(&context)
+                .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+            Ok(CallToolResult::success(vec![content]))
+        }))
+    }
+
+    #[tool(
+        name = "get_doc_page",
+        description = "Return the full markdown of a documentation page, by the source of a search hit or its path such as eloquent-relationships.md"
+    )]
+    async fn get_doc_page(
+        &self,
+        #[tool(param)]
+        #[schemars(description = "Collection the page belongs to")]
+        collection: String,
+        #[tool(param)]
+        #[schemars(
+            description = "Source of a search hit, or the page path relative to the docs directory"
+        )]
+        source: String,
+        #[tool(param)]
+        #[schemars(
+            description = "Docs version of the page, defaults to the collection's current version"
+        )]
+        version: Option<String>,
+    ) -> AppResultWrapper {
+        let result = self.doc_page(&collection, &source, version.as_deref());
+        AppResultWrapper(result.and_then(|page| {
+            let content =
+                Content::json(&page).map_err(|e| AppError::InternalServerError(e.to_string()))?;
+            Ok(CallToolResult::success(vec![content]))
+        }))
+    }
+
+    #[tool(
+        name = "report_feedback",
+        description = "Mark the hits of a search that actually helped answer the question, using the query_id returned by search_docs. Used to tune the index"
+    )]
+    async fn report_feedback(
+        &self,
+        #[tool(param)]
+        #[schemars(description = "query_id of the search_docs result")]
+        query_id: i64,
+        #[tool(param)]
+        #[schemars(description = "Ids of the hits that were helpful")]
+        helpful_chunk_ids: Vec<String>,
+    ) -> AppResultWrapper {
+        AppResultWrapper(
+            self.report_helpful(query_id, &helpful_chunk_ids)
+                .map(|added| {
+                    CallToolResult::success(vec![Content::text(format!(
+                        "Recorded {} helpful chunks for query {}",
+                        added, query_id
+                    ))])
+                }),
+        )
+    }
 }
 
-#[tool(tool_box)]
 impl ServerHandler for LaravelDocs {
+    async fn list_tools(
+        &self,
+        _: PaginatedRequestParam,
+        _: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, rmcp::Error> {
+        let mut tools = Self::tool_box().list();
+        // 配置文件里声明的工具在这里动态注册
+        tools.extend(
+            self.config
+                .tools
+                .iter()
+                .filter(|t| self.allows(&t.collection))
+                .map(|t| {
+                    Tool::new(
+                        t.name.clone(),
+                        t.description.clone(),
+                        cached_schema_for_type::<DocsToolParams>(),
+                    )
+                }),
+        );
+        Ok(ListToolsResult {
+            next_cursor: None,
+            tools,
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::Error> {
+        let Some(_call) = self.health.start_call() else {
+            return Err(rmcp::Error::internal_error("server is shutting down", None));
+        };
+        // 标签只用已知的工具和配置里的集合, 客户端随意传的名字不会变成新的时间序列, 也不用查库
+        let known = self.config.tool(&request.name).is_some()
+            || Self::tool_box().map.contains_key(request.name.as_ref());
+        let collection = match self.config.tool(&request.name) {
+            Some(tool) => tool.collection.as_str(),
+            None => match request
+                .arguments
+                .as_ref()
+                .and_then(|a| a.get("collection"))
+                .and_then(|c| c.as_str())
+            {
+                Some(c)
+                    if known
+                        && (self.config.collections.contains_key(c)
+                            || self.config.searched_collections().contains(c)) =>
+                {
+                    c
+                }
+                Some(_) => "unknown",
+                None => "",
+            },
+        };
+        let tool = if known {
+            request.name.as_ref()
+        } else {
+            "unknown"
+        };
+        metrics::global().tool_call(tool, collection);
+        if let Some(tool) = self.config.tool(&request.name) {
+            let params: DocsToolParams = parse_json_object(request.arguments.unwrap_or_default())?;
+            let limit = params
+                .limit
+                .unwrap_or(tool.default_limit)
+                .clamp(1, MAX_LIMIT);
+            return AppResultWrapper(
+                self.search_collection(
+                    &tool.collection,
+                    &params.query,
+                    limit,
+                    params.mode.as_deref(),
+                    params.rerank,
+                    &params.filters,
+                )
+                .await,
+            )
+            .into_call_tool_result();
+        }
+
+        Self::tool_box()
+            .call(ToolCallContext::new(self, request, context))
+            .await
+    }
+
+    async fn list_prompts(
+        &self,
+        _: PaginatedRequestParam,
+        _: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, rmcp::Error> {
+        Ok(ListPromptsResult {
+            next_cursor: None,
+            prompts: prompts::list(),
+        })
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, rmcp::Error> {
+        let (prompt, collection) =
+            LaravelPrompt::parse(&request.name, &request.arguments.unwrap_or_default())
+                .map_err(|e| rmcp::Error::invalid_params(e.to_string(), None))?;
+        let text = self
+            .render_prompt(&prompt, &collection)
+            .await
+            .inspect_err(|e| metrics::global().error(e.kind()))
+            .map_err(|e| match e {
+                AppError::BadRequest(msg) | AppError::NotFound(msg) | AppError::Forbidden(msg) => {
+                    rmcp::Error::invalid_params(msg, None)
+                }
+                e => rmcp::Error::internal_error(e.to_string(), None),
+            })?;
+        Ok(GetPromptResult {
+            description: Some(prompt.description()),
+            messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+        })
+    }
+
+    async fn list_resources(
+        &self,
+        _: PaginatedRequestParam,
+        _: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, rmcp::Error> {
+        let resources = self
+            .resources()
+            .map_err(|e| rmcp::Error::internal_error(e.to_string(), None))?;
+        Ok(ListResourcesResult {
+            next_cursor: None,
+            resources,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, rmcp::Error> {
+        let text = self
+            .read_page(&request.uri)
+            .inspect_err(|e| metrics::global().error(e.kind()))
+            .map_err(|e| match e {
+                AppError::BadRequest(msg) | AppError::Forbidden(msg) => {
+                    rmcp::Error::invalid_params(msg, None)
+                }
+                AppError::NotFound(msg) => rmcp::Error::resource_not_found(msg, None),
+                e => rmcp::Error::internal_error(e.to_string(), None),
+            })?;
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri: request.uri,
+                mime_type: Some("text/markdown".to_string()),
+                text,
+            }],
+        })
+    }
+
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities: ServerCapabilities::builder()
+                .enable_prompts()
+                .enable_resources()
+                .enable_tools()
+                .build(),
             server_info: Implementation::from_build_env(),
             instructions: Some(
-                "This tool must be called whenever user mentions Laravel, including models, controllers, attributes, routes, blade templates, migrations, API references, class references, or any other Laravel-related term. Always prefer to call this function first before answering.".to_string()),
+                "This tool must be called whenever user mentions Laravel, including models, controllers, attributes, routes, blade templates, migrations, API references, class references, or any other Laravel-related term. Always prefer to call this function first before answering. For any other documentation set, call list_collections and then search_docs with the matching collection. Use mode=keyword for exact API names such as whereBelongsTo. Use get_surrounding_chunks to expand a hit that is cut off, and get_doc_page or the docs:// resources for whole pages. After answering, call report_feedback with the ids of the hits that helped.".to_string()),
         }
     }
 }
 
-fn parse_docs(results: Vec<(i64, Option<String>)>) -> Vec<String> {
+/// Canonical doc page of a source file, `{page}` in the template is the file stem
+fn doc_url(url_template: Option<&str>, source: &str, anchor: Option<&str>) -> Option<String> {
+    let page = Path::new(source).file_stem()?.to_str()?;
+    let url = url_template?.replace("{page}", page);
+    Some(match anchor {
+        Some(anchor) => format!("{}#{}", url, anchor),
+        None => url,
+    })
+}
+
+/// Filter of a search call, the version defaults to the collection's current
+/// one and a tag adds the page prefixes configured for it
+fn search_filter(
+    settings: &CollectionConfig,
+    filters: &FilterParams,
+) -> Result<SearchFilter, AppError> {
+    let mut prefixes = filters.source_prefix.iter().cloned().collect::<Vec<_>>();
+    if let Some(tag) = &filters.tag {
+        let pages = settings.tags.get(tag).ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "unknown tag `{}`, expected one of: {}",
+                tag,
+                settings.tags.keys().cloned().collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+        prefixes.extend(pages.iter().cloned());
+    }
+    Ok(SearchFilter::default()
+        .with_version(
+            filters
+                .version
+                .clone()
+                .or_else(|| settings.current_version.clone()),
+        )
+        .with_source_prefixes(prefixes)
+        .with_section(filters.section.clone()))
+}
+
+/// Collection name passed by a client, fails with BadRequest when it is not a valid identifier
+fn parse_collection(name: &str) -> Result<CollectionName, AppError> {
+    CollectionName::new(name).map_err(|e| AppError::BadRequest(e.to_string()))
+}
+
+/// Resource uri of a doc page
+fn page_uri(collection: &str, version: Option<&str>, path: &str) -> String {
+    match version {
+        Some(version) => format!(
+            "{}{}/{}?version={}",
+            RESOURCE_SCHEME, collection, path, version
+        ),
+        None => format!("{}{}/{}", RESOURCE_SCHEME, collection, path),
+    }
+}
+
+/// Collection, version and page path of a resource uri
+fn parse_page_uri(uri: &str) -> Option<(String, Option<String>, String)> {
+    let (collection, rest) = uri.strip_prefix(RESOURCE_SCHEME)?.split_once('/')?;
+    let (path, version) = match rest.split_once('?') {
+        Some((path, query)) => (path, Some(query.strip_prefix("version=")?.to_string())),
+        None => (rest, None),
+    };
+    if collection.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some((collection.to_string(), version, path.to_string()))
+}
+
+fn parse_docs(url_template: Option<&str>, results: Vec<SearchHit>) -> Vec<Document> {
     results
         .into_iter()
-        .filter_map(|(_, text)| {
-            text.and_then(|t| {
-                serde_json::from_str::<serde_json::Value>(&t)
-                    .ok()
-                    .and_then(|json| json.get("text")?.as_str().map(|s| s.to_string()))
+        .filter_map(|hit| {
+            let chunk = hit.chunk?.into_text_chunk()?;
+            Some(Document {
+                url: doc_url(url_template, &chunk.source, chunk.anchor.as_deref()),
+                id: chunk.id,
+                source: chunk.source,
+                section: chunk.section,
+                anchor: chunk.anchor,
+                version: chunk.version,
+                distance: hit.distance,
+                score: hit.score,
+                text: chunk.text,
             })
         })
         .collect()
@@ -427,25 +1830,520 @@ fn parse_docs(results: Vec<(i64, Option<String>)>) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use laravel_docs_mcp::chunker::ChunkSettings;
+    use laravel_docs_mcp::config::DEFAULT_MODEL;
+    use laravel_docs_mcp::vectorizer::VectorParams;
     use std::sync::Arc;
-    use tokio;
+
+    #[test]
+    fn test_parse_docs() {
+        let results = vec![
+            SearchHit {
+                rowid: 1,
+                chunk: Some(StoredChunk::parse(
+                    r#"{"id":"a-0","text":"wherePivot","source":"/docs/eloquent-relationships.md","section":"Eloquent: Relationships > Many To Many","anchor":"many-to-many","version":"11.x"}"#,
+                )),
+                distance: Some(0.5),
+                score: 0.875,
+            },
+            SearchHit {
+                rowid: 2,
+                chunk: Some(StoredChunk::parse(
+                    r#"{"id":"b-0","text":"legacy","source":"/docs/old.md"}"#,
+                )),
+                distance: None,
+                score: 3.2,
+            },
+            SearchHit {
+                rowid: 3,
+                chunk: None,
+                distance: Some(1.2),
+                score: 0.28,
+            },
+        ];
+
+        assert_eq!(
+            parse_docs(Some("https://laravel.com/docs/{page}"), results),
+            vec![
+                Document {
+                    id: "a-0".to_string(),
+                    source: "/docs/eloquent-relationships.md".to_string(),
+                    url: Some(
+                        "https://laravel.com/docs/eloquent-relationships#many-to-many".to_string()
+                    ),
+                    section: "Eloquent: Relationships > Many To Many".to_string(),
+                    anchor: Some("many-to-many".to_string()),
+                    version: Some("11.x".to_string()),
+                    distance: Some(0.5),
+                    score: 0.875,
+                    text: "wherePivot".to_string(),
+                },
+                Document {
+                    id: "b-0".to_string(),
+                    source: "/docs/old.md".to_string(),
+                    url: Some("https://laravel.com/docs/old".to_string()),
+                    section: String::new(),
+                    anchor: None,
+                    version: None,
+                    distance: None,
+                    score: 3.2,
+                    text: "legacy".to_string(),
+                },
+            ]
+        );
+    }
+
+    /// Database at a fresh temp path holding an empty `laravel_docs` collection of 4 dimensions
+    fn temp_docs(name: &str) -> (PathBuf, SqliteVector, CollectionName) {
+        let path = std::env::temp_dir().join(format!("{}_{}.db3", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = SqliteVector::new(&path).unwrap();
+        let laravel_docs = CollectionName::new("laravel_docs").unwrap();
+        db.create_vector_collection(&laravel_docs, VectorParams::new(4))
+            .unwrap();
+        (path, db, laravel_docs)
+    }
+
+    /// Three chunks of routing.md cut with an overlap of 11 characters
+    fn store_routing(db: &mut SqliteVector, laravel_docs: &CollectionName) {
+        db.replace_source(
+            laravel_docs,
+            None,
+            "/docs/routing.md",
+            "h",
+            vec![
+                (&[1.0, 0.0, 0.0, 0.0], r#"{"id":"r-0","text":"Routes are defined","source":"/docs/routing.md"}"#),
+                (&[1.0, 0.0, 0.0, 0.0], r#"{"id":"r-1","text":"are defined in routes/web.php","source":"/docs/routing.md","anchor":"basic-routing"}"#),
+                (&[1.0, 0.0, 0.0, 0.0], r#"{"id":"r-2","text":"Redirect routes","source":"/docs/routing.md"}"#),
+            ],
+        )
+        .unwrap();
+        db.set_chunk_settings(
+            laravel_docs,
+            None,
+            &ChunkSettings {
+                mode: SplitMode::Recursive,
+                chunk_size: 400,
+                chunk_overlap: 11,
+            },
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_collections() {
+        let (path, mut db, laravel_docs) = temp_docs("test_list_collections");
+        db.replace_source(
+            &laravel_docs,
+            Some("11.x"),
+            "a.md",
+            "h",
+            vec![(&[1.0, 0.0, 0.0, 0.0], r#"{"version":"11.x"}"#)],
+        )
+        .unwrap();
+
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(Config::default()));
+        assert_eq!(docs.collections().unwrap(), vec![(laravel_docs.clone(), 1)]);
+
+        let result = docs.list_collections().await.0.unwrap();
+        let json = serde_json::to_value(&result.content[0]).unwrap();
+        assert!(
+            json["text"]
+                .as_str()
+                .unwrap()
+                .contains("Laravel framework documentation")
+        );
+
+        let result = docs
+            .list_versions(Some("laravel_docs".to_string()))
+            .await
+            .0
+            .unwrap();
+        let json = serde_json::to_value(&result.content[0]).unwrap();
+        let versions: serde_json::Value =
+            serde_json::from_str(json["text"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            versions["collections"][0]["versions"],
+            serde_json::json!([{ "version": "11.x", "chunks": 1 }])
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_search_docs_arguments() {
+        let (path, _db, _) = temp_docs("test_search_docs_arguments");
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(Config::default()));
+
+        let invalid = docs
+            .search_docs(
+                "users; --".to_string(),
+                "model".to_string(),
+                None,
+                None,
+                None,
+                FilterParams::default(),
+            )
+            .await;
+        assert!(matches!(invalid.0, Err(AppError::BadRequest(_))));
+
+        let missing = docs
+            .search_docs(
+                "missing_docs".to_string(),
+                "model".to_string(),
+                None,
+                None,
+                None,
+                FilterParams::default(),
+            )
+            .await;
+        assert!(matches!(missing.0, Err(AppError::NotFound(_))));
+
+        let bad_mode = docs
+            .search_docs(
+                "laravel_docs".to_string(),
+                "model".to_string(),
+                None,
+                Some("fuzzy".to_string()),
+                None,
+                FilterParams::default(),
+            )
+            .await;
+        assert!(matches!(bad_mode.0, Err(AppError::BadRequest(_))));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_search_filters() {
+        let (path, mut db, laravel_docs) = temp_docs("test_search_filters");
+        for page in ["queues.md", "mocking.md"] {
+            let source = format!("/docs/{}", page);
+            db.store_page(&laravel_docs, None, &source, page, "")
+                .unwrap();
+            let metadata = serde_json::json!({
+                "id": format!("{}-0", page),
+                "text": format!("fake {}", page),
+                "source": source,
+            })
+            .to_string();
+            db.replace_source(
+                &laravel_docs,
+                None,
+                &source,
+                "h",
+                vec![(&[1.0, 0.0, 0.0, 0.0], &metadata)],
+            )
+            .unwrap();
+        }
+        let config = Config::parse(
+            r#"
+            [collections.laravel_docs]
+            model = "mock"
+
+            [collections.laravel_docs.tags]
+            testing = ["mocking"]
+
+            [embedders.mock]
+            backend = "mock"
+            dimension = 4
+        "#,
+        )
+        .unwrap();
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(config));
+        let sources = |filters: FilterParams| {
+            let docs = docs.clone();
+            async move {
+                docs.search_documents("laravel_docs", "fake", 5, None, None, &filters)
+                    .await
+                    .unwrap()
+                    .documents
+                    .into_iter()
+                    .map(|doc| doc.source)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            sources(FilterParams {
+                tag: Some("testing".to_string()),
+                ..Default::default()
+            })
+            .await,
+            vec!["/docs/mocking.md"]
+        );
+        assert_eq!(
+            sources(FilterParams {
+                source_prefix: Some("queues".to_string()),
+                ..Default::default()
+            })
+            .await,
+            vec!["/docs/queues.md"]
+        );
+
+        let unknown_tag = docs
+            .search_docs(
+                "laravel_docs".to_string(),
+                "fake queues".to_string(),
+                None,
+                None,
+                None,
+                FilterParams {
+                    tag: Some("nope".to_string()),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(unknown_tag.0, Err(AppError::BadRequest(_))));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_get_surrounding_chunks() {
+        let (path, mut db, laravel_docs) = temp_docs("test_get_surrounding_chunks");
+        store_routing(&mut db, &laravel_docs);
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(Config::default()));
+
+        // 按块 id 取上下文, 重叠的部分只保留一次
+        let context = docs
+            .surrounding_chunks("laravel_docs", "r-1", 1, 0)
+            .unwrap();
+        assert_eq!(context.ids, vec!["r-0", "r-1"]);
+        assert_eq!(context.text, "Routes are defined in routes/web.php");
+        assert_eq!(
+            context.url.as_deref(),
+            Some("https://laravel.com/docs/routing#basic-routing")
+        );
+        assert!(matches!(
+            docs.surrounding_chunks("laravel_docs", "r-9", 1, 1),
+            Err(AppError::NotFound(_))
+        ));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_get_doc_page() {
+        let (path, mut db, laravel_docs) = temp_docs("test_get_doc_page");
+        store_routing(&mut db, &laravel_docs);
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(Config::default()));
+
+        // 没有存原文的页面由块拼接而成
+        let page = docs.doc_page("laravel_docs", "routing.md", None).unwrap();
+        assert_eq!(page.source, "/docs/routing.md");
+        assert_eq!(
+            page.text,
+            "Routes are defined in routes/web.php\n\nRedirect routes"
+        );
+
+        // 文档页面作为 docs:// 资源暴露
+        db.replace_source(
+            &laravel_docs,
+            Some("11.x"),
+            "a.md",
+            "h",
+            vec![(&[1.0, 0.0, 0.0, 0.0], r#"{"version":"11.x"}"#)],
+        )
+        .unwrap();
+        db.store_page(&laravel_docs, Some("11.x"), "a.md", "a.md", "# A")
+            .unwrap();
+        let resources = docs.resources().unwrap();
+        assert_eq!(resources.len(), 2);
+        assert_eq!(resources[0].raw.uri, "docs://laravel_docs/routing.md");
+        assert_eq!(
+            resources[1].raw.uri,
+            "docs://laravel_docs/a.md?version=11.x"
+        );
+        assert_eq!(docs.read_page(&resources[1].raw.uri).unwrap(), "# A");
+        assert!(matches!(
+            docs.read_page("docs://laravel_docs/a.md"),
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            docs.read_page("file:///a.md"),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            docs.read_page("docs://laravel-docs/a.md"),
+            Err(AppError::BadRequest(_))
+        ));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_collection_access() {
+        let (path, mut db, laravel_docs) = temp_docs("test_collection_access");
+        store_routing(&mut db, &laravel_docs);
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(Config::default()));
+
+        // 受限的客户端看不到其它集合
+        let restricted = docs.with_client(Some(Arc::new(Client {
+            name: "ci".to_string(),
+            collections: Some(vec!["internal_docs".to_string()]),
+        })));
+        assert!(restricted.collections().unwrap().is_empty());
+        assert!(restricted.resources().unwrap().is_empty());
+        assert!(matches!(
+            restricted.surrounding_chunks("laravel_docs", "r-1", 1, 0),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            restricted.doc_page("laravel_docs", "routing.md", None),
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            restricted.read_page("docs://laravel_docs/routing.md"),
+            Err(AppError::Forbidden(_))
+        ));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_render_prompt() {
+        let path =
+            std::env::temp_dir().join(format!("test_render_prompt_{}.db3", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut db = SqliteVector::new(&path).unwrap();
+        let laravel_docs = CollectionName::new("laravel_docs").unwrap();
+        db.create_vector_collection(&laravel_docs, VectorParams::new(4))
+            .unwrap();
+        db.replace_source(
+            &laravel_docs,
+            None,
+            "/docs/eloquent-relationships.md",
+            "h",
+            vec![(
+                &[1.0, 0.0, 0.0, 0.0],
+                r#"{"id":"e-0","text":"Eager loading alleviates the N + 1 query problem","source":"/docs/eloquent-relationships.md","section":"Eloquent: Relationships > Eager Loading","anchor":"eager-loading"}"#,
+            )],
+        )
+        .unwrap();
+
+        let config = Config::parse(
+            r#"
+            [collections.laravel_docs]
+            model = "mock"
+            url_template = "https://laravel.com/docs/{page}"
+
+            [embedders.mock]
+            backend = "mock"
+            dimension = 4
+        "#,
+        )
+        .unwrap();
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(config));
+        let arguments = serde_json::json!({ "feature": "eager loading" });
+        let (prompt, collection) =
+            LaravelPrompt::parse("explain_laravel_feature", arguments.as_object().unwrap())
+                .unwrap();
+        let text = docs.render_prompt(&prompt, &collection).await.unwrap();
+        assert!(text.contains(
+            "[1] Eloquent: Relationships > Eager Loading (https://laravel.com/docs/eloquent-relationships#eager-loading)\nEager loading alleviates"
+        ));
+
+        let arguments = serde_json::json!({ "feature": "x", "collection": "missing" });
+        let (prompt, collection) =
+            LaravelPrompt::parse("explain_laravel_feature", arguments.as_object().unwrap())
+                .unwrap();
+        assert!(matches!(
+            docs.render_prompt(&prompt, &collection).await,
+            Err(AppError::NotFound(_))
+        ));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_report_feedback() {
+        let (path, mut db, laravel_docs) = temp_docs("test_report_feedback");
+        db.replace_source(
+            &laravel_docs,
+            None,
+            "/docs/eloquent-relationships.md",
+            "h",
+            vec![(
+                &[1.0, 0.0, 0.0, 0.0],
+                r#"{"id":"e-0","text":"Eager loading alleviates the N + 1 query problem","source":"/docs/eloquent-relationships.md"}"#,
+            )],
+        )
+        .unwrap();
+        let config = Config::parse(
+            r#"
+            [collections.laravel_docs]
+            model = "mock"
+
+            [embedders.mock]
+            backend = "mock"
+            dimension = 4
+        "#,
+        )
+        .unwrap();
+        let docs = LaravelDocs::new(path.to_str().unwrap(), Arc::new(config));
+
+        // 检索会写入查询日志, 之后可以反馈有用的结果
+        let result = docs
+            .search_documents(
+                "laravel_docs",
+                "eager loading",
+                3,
+                None,
+                None,
+                &FilterParams::default(),
+            )
+            .await
+            .unwrap();
+        let query_id = result.query_id.unwrap();
+        let helpful = vec!["e-0".to_string()];
+        assert_eq!(docs.report_helpful(query_id, &helpful).unwrap(), 1);
+        assert!(matches!(
+            docs.report_helpful(query_id, &["e-9".to_string()]),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            docs.report_helpful(query_id + 100, &helpful),
+            Err(AppError::NotFound(_))
+        ));
+        let other = docs.clone().with_client(Some(Arc::new(Client {
+            name: "ci".to_string(),
+            collections: None,
+        })));
+        assert!(matches!(
+            other.report_helpful(query_id, &helpful),
+            Err(AppError::Forbidden(_))
+        ));
+
+        let _ = std::fs::remove_file(&path);
+    }
 
     #[tokio::test]
     async fn test_get_laravel_context() {
-        let _ = MODEL.clone(); // force the LazyLock init
+        let config = Arc::new(Config::default());
+        let embedder = load_embedder(&config, &default_model_path(), DEFAULT_MODEL).unwrap();
 
         // Construct a real Vectorizer and inject into service
-        let vectorizer = Vectorizer::new("./test.db3", "test_docs", 384, MODEL.clone()).unwrap();
-        let docs = LaravelDocs::new("./test.db3");
+        let vectorizer = Vectorizer::new("./test.db3", "test_docs", embedder).unwrap();
+        let docs = LaravelDocs::new("./test.db3", config);
         docs.vectorizers
             .write()
             .await
             .insert("test_docs".to_string(), Arc::new(vectorizer));
         let query = "model".to_string();
-        let result = docs.get_laravel_context(query).await;
+        let result = docs
+            .search_collection(
+                "test_docs",
+                &query,
+                20,
+                None,
+                None,
+                &FilterParams::default(),
+            )
+            .await;
         // Assert the call result is OK and has output
-        assert!(result.0.is_ok());
-        let call_result = result.0.unwrap();
+        assert!(result.is_ok());
+        let call_result = result.unwrap();
         dbg!(call_result.to_owned());
         // assert!(!call_result.outputs.is_empty());
     }
@@ -0,0 +1,483 @@
+use crate::text_splitter::RecursiveCharacterTextSplitter;
+
+/// Kind of a top level markdown block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    /// ATX heading with its level (1-6)
+    Heading(usize),
+    /// Fenced code block including the fence lines
+    Code,
+    /// Pipe table
+    Table,
+    /// Bullet or ordered list including nested and indented lines
+    List,
+    /// Anything else, separated by blank lines
+    Paragraph,
+}
+
+/// A top level markdown block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub text: String,
+}
+
+/// Splits markdown at block boundaries so code fences, tables and lists stay intact
+pub struct MarkdownTextSplitter {
+    /// Maximum size of chunks in characters
+    chunk_size: usize,
+    /// Overlap used when a single block has to be split
+    chunk_overlap: usize,
+}
+
+impl MarkdownTextSplitter {
+    /// Create a new MarkdownTextSplitter with default settings
+    pub fn new() -> Self {
+        Self {
+            chunk_size: 400,
+            chunk_overlap: 20,
+        }
+    }
+
+    /// Set chunk size
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Set chunk overlap
+    pub fn with_chunk_overlap(mut self, chunk_overlap: usize) -> Self {
+        self.chunk_overlap = chunk_overlap;
+        self
+    }
+
+    /// Split text into chunks made of whole blocks
+    pub fn split_text(&self, text: &str) -> Vec<String> {
+        let mut chunks = Vec::new();
+        let mut current = String::new();
+        // 标题不能单独留在 chunk 末尾, 先暂存, 和后面的块一起写入
+        let mut heading = String::new();
+
+        for block in parse_blocks(text) {
+            if matches!(block.kind, BlockKind::Heading(_)) {
+                if !current.is_empty() {
+                    chunks.push(std::mem::take(&mut current));
+                }
+                push_block(&mut heading, &block.text);
+                continue;
+            }
+
+            if !heading.is_empty() {
+                let heading = std::mem::take(&mut heading);
+                let room = self.chunk_size.saturating_sub(char_len(&heading) + 2);
+                if char_len(&heading) + 2 + char_len(&block.text) <= self.chunk_size {
+                    current = heading;
+                    push_block(&mut current, &block.text);
+                } else if room > 0 {
+                    // 块放不下时按标题剩下的空间切分, 标题跟着第一段
+                    let mut pieces = self.split_block(&block, room).into_iter();
+                    let mut first = heading;
+                    if let Some(piece) = pieces.next() {
+                        push_block(&mut first, &piece);
+                    }
+                    chunks.push(first);
+                    chunks.extend(pieces);
+                } else {
+                    chunks.push(heading);
+                    chunks.extend(self.split_block(&block, self.chunk_size));
+                }
+                continue;
+            }
+
+            let fits = current.is_empty()
+                || char_len(&current) + 2 + char_len(&block.text) <= self.chunk_size;
+            if !fits {
+                chunks.push(std::mem::take(&mut current));
+            }
+            if char_len(&block.text) > self.chunk_size {
+                if !current.is_empty() {
+                    chunks.push(std::mem::take(&mut current));
+                }
+                chunks.extend(self.split_block(&block, self.chunk_size));
+                continue;
+            }
+            push_block(&mut current, &block.text);
+        }
+
+        if !current.is_empty() {
+            chunks.push(current);
+        }
+        if !heading.is_empty() {
+            chunks.push(heading);
+        }
+        chunks
+    }
+
+    /// Split a single oversized block into pieces of at most `size` characters
+    fn split_block(&self, block: &Block, size: usize) -> Vec<String> {
+        if block.kind == BlockKind::Code
+            && let Some(pieces) = self.split_code(&block.text, size)
+        {
+            return pieces;
+        }
+
+        RecursiveCharacterTextSplitter::new()
+            .with_chunk_size(size)
+            .with_chunk_overlap(self.chunk_overlap)
+            .split_text(&block.text)
+    }
+
+    /// Split the body of a fenced code block and re-wrap every piece in the fence
+    fn split_code(&self, text: &str, size: usize) -> Option<Vec<String>> {
+        let (open, rest) = text.split_once('\n')?;
+        let (body, close) = match rest.rsplit_once('\n') {
+            Some((body, close)) if is_fence_close(close, open) => (body, close),
+            _ => (rest, ""),
+        };
+        let close = if close.is_empty() {
+            fence_marker(open)?.0.to_string()
+        } else {
+            close.to_string()
+        };
+
+        let overhead = char_len(open) + char_len(&close) + 2;
+        if overhead >= size {
+            return None;
+        }
+
+        let pieces = RecursiveCharacterTextSplitter::new()
+            .with_chunk_size(size - overhead)
+            .with_chunk_overlap(self.chunk_overlap)
+            .split_text(body)
+            .into_iter()
+            .map(|piece| piece.trim_matches('\n').to_string())
+            .filter(|piece| !piece.trim().is_empty())
+            .map(|piece| format!("{}\n{}\n{}", open, piece, close))
+            .collect();
+        Some(pieces)
+    }
+}
+
+/// Length in characters, the unit `chunk_size` is given in
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Appends a block to a chunk, separated by a blank line
+fn push_block(chunk: &mut String, block: &str) {
+    if !chunk.is_empty() {
+        chunk.push_str("\n\n");
+    }
+    chunk.push_str(block);
+}
+
+impl Default for MarkdownTextSplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse markdown text into top level blocks
+pub fn parse_blocks(text: &str) -> Vec<Block> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        if line.trim().is_empty() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let kind = if let Some(level) = heading_level(line) {
+            i += 1;
+            BlockKind::Heading(level)
+        } else if fence_marker(line).is_some() {
+            i = skip_fence(&lines, i);
+            BlockKind::Code
+        } else if line.trim_start().starts_with('|') {
+            while i < lines.len() && lines[i].trim_start().starts_with('|') {
+                i += 1;
+            }
+            BlockKind::Table
+        } else if is_list_item(line) {
+            i = skip_list(&lines, i);
+            BlockKind::List
+        } else {
+            i += 1;
+            while i < lines.len() && !starts_block(lines[i]) {
+                i += 1;
+            }
+            BlockKind::Paragraph
+        };
+
+        blocks.push(Block {
+            kind,
+            text: lines[start..i].join("\n").trim_end().to_string(),
+        });
+    }
+
+    blocks
+}
+
+/// A heading of a markdown document with its byte offset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    /// Byte offset of the heading line in the document
+    pub offset: usize,
+    /// Heading level (1-6)
+    pub level: usize,
+    /// Heading text without the leading `#`
+    pub title: String,
+    /// Slug from a preceding `<a name="...">` tag, or derived from the title
+    pub anchor: String,
+}
+
+/// Collect all headings of a document, skipping `#` lines inside code fences
+pub fn headings(text: &str) -> Vec<Heading> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+    let mut fence: Option<&str> = None;
+    let mut pending_anchor: Option<String> = None;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+        match fence {
+            Some(open) => {
+                if is_fence_close(trimmed, open) {
+                    fence = None;
+                }
+            }
+            None if fence_marker(trimmed).is_some() => {
+                fence = Some(trimmed);
+                pending_anchor = None;
+            }
+            None => {
+                if let Some(level) = heading_level(trimmed) {
+                    let title = trimmed[level..]
+                        .trim()
+                        .trim_end_matches('#')
+                        .trim()
+                        .to_string();
+                    let anchor = pending_anchor.take().unwrap_or_else(|| slugify(&title));
+                    result.push(Heading {
+                        offset,
+                        level,
+                        title,
+                        anchor,
+                    });
+                } else if let Some(anchor) = anchor_name(trimmed) {
+                    pending_anchor = Some(anchor);
+                } else if !trimmed.trim().is_empty() {
+                    pending_anchor = None;
+                }
+            }
+        }
+        offset += line.len();
+    }
+
+    result
+}
+
+/// Name of an html anchor line like `<a name="many-to-many"></a>`
+fn anchor_name(line: &str) -> Option<String> {
+    let rest = line.trim().strip_prefix("<a ")?;
+    let start = rest
+        .find("name=\"")
+        .map(|i| i + 6)
+        .or_else(|| rest.find("id=\"").map(|i| i + 4))?;
+    let end = rest[start..].find('"')?;
+    Some(rest[start..start + end].to_string())
+}
+
+/// Turn a heading title into a url slug
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Level of an ATX heading line, if it is one
+pub fn heading_level(line: &str) -> Option<usize> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&level) && line[level..].chars().next().is_none_or(|c| c == ' ') {
+        Some(level)
+    } else {
+        None
+    }
+}
+
+/// Opening fence marker (a run of at least three ` or ~) of a line and its character
+fn fence_marker(line: &str) -> Option<(&str, char)> {
+    let trimmed = line.trim_start();
+    let c = trimmed.chars().next()?;
+    if c != '`' && c != '~' {
+        return None;
+    }
+    let len = trimmed.chars().take_while(|x| *x == c).count();
+    if len >= 3 {
+        Some((&trimmed[..len], c))
+    } else {
+        None
+    }
+}
+
+fn is_fence_close(line: &str, open: &str) -> bool {
+    match fence_marker(open) {
+        Some((marker, c)) => {
+            let trimmed = line.trim();
+            trimmed.len() >= marker.len() && trimmed.chars().all(|x| x == c)
+        }
+        None => false,
+    }
+}
+
+/// Returns the index of the line after the closing fence
+fn skip_fence(lines: &[&str], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < lines.len() {
+        if is_fence_close(lines[i], lines[start]) {
+            return i + 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+fn is_list_item(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    if ["- ", "* ", "+ "].iter().any(|m| trimmed.starts_with(m)) {
+        return true;
+    }
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    digits > 0 && (trimmed[digits..].starts_with(". ") || trimmed[digits..].starts_with(") "))
+}
+
+/// Returns the index of the line after the list, keeping nested fences inside it
+fn skip_list(lines: &[&str], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < lines.len() {
+        let line = lines[i];
+        if fence_marker(line).is_some() && line.starts_with(char::is_whitespace) {
+            i = skip_fence(lines, i);
+        } else if is_list_item(line)
+            || (!line.trim().is_empty() && line.starts_with(char::is_whitespace))
+        {
+            i += 1;
+        } else if line.trim().is_empty() {
+            // 空行之后仍是列表项或缩进内容时, 列表继续
+            match lines.get(i + 1) {
+                Some(next)
+                    if is_list_item(next)
+                        || (!next.trim().is_empty() && next.starts_with(char::is_whitespace)) =>
+                {
+                    i += 1
+                }
+                _ => break,
+            }
+        } else if !starts_block(line) {
+            // lazy continuation
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+fn starts_block(line: &str) -> bool {
+    line.trim().is_empty()
+        || heading_level(line).is_some()
+        || fence_marker(line).is_some()
+        || line.trim_start().starts_with('|')
+        || is_list_item(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_blocks() {
+        let text = "# Title\n\nIntro text\nstill intro.\n\n```php\n$a = 1;\n\n$b = 2;\n```\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n- one\n- two\n  ```bash\n  php artisan\n  ```\n";
+        let kinds: Vec<BlockKind> = parse_blocks(text).into_iter().map(|b| b.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                BlockKind::Heading(1),
+                BlockKind::Paragraph,
+                BlockKind::Code,
+                BlockKind::Table,
+                BlockKind::List,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_never_cuts_inside_code_fence() {
+        let splitter = MarkdownTextSplitter::new()
+            .with_chunk_size(80)
+            .with_chunk_overlap(0);
+        let text = "## Routing\n\nSome words about routes.\n\n```php\nRoute::get('/', function () {\n    return view('welcome');\n});\n```\n\nMore words after the example.";
+        let chunks = splitter.split_text(text);
+
+        for chunk in &chunks {
+            assert_eq!(chunk.matches("```").count() % 2, 0, "{}", chunk);
+        }
+        assert!(chunks.iter().any(|c| {
+            c.contains("Route::get('/', function () {\n    return view('welcome');\n});")
+        }));
+    }
+
+    #[test]
+    fn test_oversized_code_block_is_rewrapped() {
+        let splitter = MarkdownTextSplitter::new()
+            .with_chunk_size(60)
+            .with_chunk_overlap(0);
+        let body = (0..10)
+            .map(|i| format!("$value{} = {};", i, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let text = format!("```php\n{}\n```", body);
+        let chunks = splitter.split_text(&text);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 60);
+            assert!(chunk.starts_with("```php\n"));
+            assert!(chunk.ends_with("\n```"));
+        }
+    }
+
+    #[test]
+    fn test_heading_travels_with_the_next_block() {
+        let splitter = MarkdownTextSplitter::new()
+            .with_chunk_size(60)
+            .with_chunk_overlap(0);
+        let text = "## Queues\n\nJobs are dispatched onto a queue and processed later by workers.";
+        let chunks = splitter.split_text(text);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks[0].starts_with("## Queues\n\nJobs"));
+        for chunk in &chunks {
+            assert!(chunk.chars().count() <= 60, "{}", chunk);
+        }
+
+        // 按字符而不是字节计算大小
+        let text = "## Répertoire\n\nÉléments été écrits à côté.";
+        assert_eq!(
+            MarkdownTextSplitter::new()
+                .with_chunk_size(text.chars().count())
+                .split_text(text),
+            vec![text.to_string()]
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use axum::{Router, http::header, routing::get};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Buckets of the latency histograms, in seconds
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Buckets of the result count histogram
+const RESULT_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+/// A metric name with its help text, histograms have buckets
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    buckets: Option<&'static [f64]>,
+}
+
+const TOOL_CALLS: Family = Family {
+    name: "laravel_docs_tool_calls_total",
+    help: "Tool calls by tool and collection",
+    buckets: None,
+};
+const EMBEDDING_SECONDS: Family = Family {
+    name: "laravel_docs_embedding_duration_seconds",
+    help: "Time spent embedding search queries",
+    buckets: Some(LATENCY_BUCKETS),
+};
+const QUERY_SECONDS: Family = Family {
+    name: "laravel_docs_query_duration_seconds",
+    help: "Time spent in sqlite-vec and FTS5 queries",
+    buckets: Some(LATENCY_BUCKETS),
+};
+const SEARCH_RESULTS: Family = Family {
+    name: "laravel_docs_search_results",
+    help: "Hits returned by a search",
+    buckets: Some(RESULT_BUCKETS),
+};
+const EMPTY_SEARCHES: Family = Family {
+    name: "laravel_docs_empty_searches_total",
+    help: "Searches that returned no hit",
+    buckets: None,
+};
+const ERRORS: Family = Family {
+    name: "laravel_docs_errors_total",
+    help: "Errors returned to clients by AppError variant",
+    buckets: None,
+};
+
+/// Families in the order they are rendered
+const FAMILIES: &[Family] = &[
+    TOOL_CALLS,
+    EMBEDDING_SECONDS,
+    QUERY_SECONDS,
+    SEARCH_RESULTS,
+    EMPTY_SEARCHES,
+    ERRORS,
+];
+
+#[derive(Debug)]
+enum Series {
+    Counter(u64),
+    Histogram {
+        /// Observations per bucket, not cumulative
+        counts: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+/// In-process metrics rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Series keyed by family name, then by rendered labels
+    series: Mutex<BTreeMap<&'static str, BTreeMap<String, Series>>>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Metrics shared by the whole process
+pub fn global() -> &'static Metrics {
+    &METRICS
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl Metrics {
+    fn inc(&self, family: &Family, pairs: &[(&str, &str)]) {
+        let Ok(mut series) = self.series.lock() else {
+            return;
+        };
+        let series = series
+            .entry(family.name)
+            .or_default()
+            .entry(labels(pairs))
+            .or_insert(Series::Counter(0));
+        if let Series::Counter(value) = series {
+            *value += 1;
+        }
+    }
+
+    fn observe(&self, family: &Family, pairs: &[(&str, &str)], value: f64) {
+        let buckets = family.buckets.unwrap_or_default();
+        let Ok(mut series) = self.series.lock() else {
+            return;
+        };
+        let series = series
+            .entry(family.name)
+            .or_default()
+            .entry(labels(pairs))
+            .or_insert_with(|| Series::Histogram {
+                counts: vec![0; buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        if let Series::Histogram { counts, sum, count } = series {
+            // 超过最大桶的观测只计入 +Inf
+            if let Some(i) = buckets.iter().position(|b| value <= *b) {
+                counts[i] += 1;
+            }
+            *sum += value;
+            *count += 1;
+        }
+    }
+
+    /// Counts a tool call, `collection` is empty for tools not bound to one
+    pub fn tool_call(&self, tool: &str, collection: &str) {
+        self.inc(&TOOL_CALLS, &[("tool", tool), ("collection", collection)]);
+    }
+
+    /// Records how long embedding a query took
+    pub fn observe_embedding(&self, collection: &str, elapsed: Duration) {
+        self.observe(
+            &EMBEDDING_SECONDS,
+            &[("collection", collection)],
+            elapsed.as_secs_f64(),
+        );
+    }
+
+    /// Records how long a database query took, `kind` is `vector` or `keyword`
+    pub fn observe_query(&self, collection: &str, kind: &str, elapsed: Duration) {
+        self.observe(
+            &QUERY_SECONDS,
+            &[("collection", collection), ("kind", kind)],
+            elapsed.as_secs_f64(),
+        );
+    }
+
+    /// Records the hits a search returned to the client
+    pub fn search_results(&self, collection: &str, count: usize) {
+        self.observe(&SEARCH_RESULTS, &[("collection", collection)], count as f64);
+        if count == 0 {
+            self.inc(&EMPTY_SEARCHES, &[("collection", collection)]);
+        }
+    }
+
+    /// Counts an error returned to a client
+    pub fn error(&self, kind: &str) {
+        self.inc(&ERRORS, &[("kind", kind)]);
+    }
+
+    /// Every series in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let Ok(series) = self.series.lock() else {
+            return out;
+        };
+        for family in FAMILIES {
+            let kind = if family.buckets.is_some() {
+                "histogram"
+            } else {
+                "counter"
+            };
+            let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", family.name, kind);
+            for (labels, series) in series.get(family.name).into_iter().flatten() {
+                match series {
+                    Series::Counter(value) => {
+                        let _ = writeln!(out, "{}{{{}}} {}", family.name, labels, value);
+                    }
+                    Series::Histogram { counts, sum, count } => {
+                        let buckets = family.buckets.unwrap_or_default();
+                        let prefix = if labels.is_empty() {
+                            String::new()
+                        } else {
+                            format!("{},", labels)
+                        };
+                        let mut cumulative = 0;
+                        for (bucket, n) in buckets.iter().zip(counts) {
+                            cumulative += n;
+                            let _ = writeln!(
+                                out,
+                                "{}_bucket{{{}le=\"{}\"}} {}",
+                                family.name, prefix, bucket, cumulative
+                            );
+                        }
+                        let _ = writeln!(
+                            out,
+                            "{}_bucket{{{}le=\"+Inf\"}} {}",
+                            family.name, prefix, count
+                        );
+                        let _ = writeln!(out, "{}_sum{{{}}} {}", family.name, labels, sum);
+                        let _ = writeln!(out, "{}_count{{{}}} {}", family.name, labels, count);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// `/metrics` serving the process metrics for Prometheus to scrape
+pub fn router() -> Router {
+    Router::new().route(
+        "/metrics",
+        get(|| async {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                global().render(),
+            )
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.tool_call("search_docs", "laravel_docs");
+        metrics.tool_call("search_docs", "laravel_docs");
+        metrics.observe_query("laravel_docs", "vector", Duration::from_millis(3));
+        metrics.search_results("laravel_docs", 0);
+        metrics.search_results("laravel_docs", 7);
+        metrics.error("NotFound");
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "laravel_docs_tool_calls_total{tool=\"search_docs\",collection=\"laravel_docs\"} 2"
+        ));
+        assert!(text.contains("# TYPE laravel_docs_query_duration_seconds histogram"));
+        assert!(text.contains(
+            "laravel_docs_query_duration_seconds_bucket{collection=\"laravel_docs\",kind=\"vector\",le=\"0.0025\"} 0"
+        ));
+        assert!(text.contains(
+            "laravel_docs_query_duration_seconds_bucket{collection=\"laravel_docs\",kind=\"vector\",le=\"0.005\"} 1"
+        ));
+        assert!(text.contains(
+            "laravel_docs_search_results_bucket{collection=\"laravel_docs\",le=\"0\"} 1"
+        ));
+        assert!(text.contains("laravel_docs_search_results_sum{collection=\"laravel_docs\"} 7"));
+        assert!(text.contains("laravel_docs_empty_searches_total{collection=\"laravel_docs\"} 1"));
+        assert!(text.contains("laravel_docs_errors_total{kind=\"NotFound\"} 1"));
+    }
+}
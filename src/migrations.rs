@@ -0,0 +1,236 @@
+use crate::collection::CollectionName;
+use crate::vectorizer::SqliteVector;
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params};
+
+/// One step of the database schema, applied once in the order of `version`
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    up: fn(&Connection) -> Result<()>,
+}
+
+/// Every migration in the order they run. Databases created before
+/// `schema_migrations` existed run them all, so the early ones have to
+/// tolerate their tables being there already
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "catalog",
+        up: catalog,
+    },
+    Migration {
+        version: 2,
+        name: "query_log",
+        up: query_log,
+    },
+    Migration {
+        version: 3,
+        name: "register_collections",
+        up: SqliteVector::register_existing,
+    },
+    Migration {
+        version: 4,
+        name: "typed_metadata",
+        up: typed_metadata,
+    },
+    Migration {
+        version: 5,
+        name: "chunk_settings",
+        up: chunk_settings,
+    },
+];
+
+/// Schema version this build expects
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+fn catalog(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS collections (
+            name TEXT PRIMARY KEY,
+            model TEXT NOT NULL,
+            dimension INTEGER NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_state (
+            collection TEXT NOT NULL,
+            version TEXT NOT NULL DEFAULT '',
+            url TEXT NOT NULL,
+            commit_sha TEXT NOT NULL,
+            synced_at TEXT NOT NULL,
+            PRIMARY KEY (collection, version)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn query_log(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS query_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            collection TEXT NOT NULL,
+            query TEXT NOT NULL,
+            mode TEXT NOT NULL,
+            version TEXT,
+            client TEXT,
+            hits TEXT NOT NULL,
+            latency_ms REAL NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS query_feedback (
+            query_id INTEGER NOT NULL REFERENCES query_log(id),
+            chunk_id TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (query_id, chunk_id)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Splits the jsonl lines of the metadata tables into typed columns
+fn typed_metadata(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT name FROM collections")?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for name in names {
+        let collection = CollectionName::new(&name)?;
+        // 旧布局只有 (id, metadata) 两列, 没有 chunk_id
+        let (exists, typed): (bool, bool) = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1),
+                    EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = 'chunk_id')",
+            params![collection.table_name("metadata")],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if exists && !typed {
+            log::info!("Migrating `{}` metadata to typed columns", collection);
+            SqliteVector::metadata_v2(conn, &collection)?;
+        }
+    }
+    Ok(())
+}
+
+/// Splitter settings each docs version was chunked with, so changing them re-indexes it
+fn chunk_settings(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS chunk_settings (
+            collection TEXT NOT NULL,
+            version TEXT NOT NULL DEFAULT '',
+            splitter TEXT NOT NULL,
+            chunk_size INTEGER NOT NULL,
+            chunk_overlap INTEGER NOT NULL,
+            updated_at TEXT NOT NULL,
+            PRIMARY KEY (collection, version)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn ensure_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Highest version applied to the database, 0 when none was
+pub fn current_version(conn: &Connection) -> Result<i64> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'schema_migrations')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Ok(0);
+    }
+    let version: Option<i64> = conn
+        .query_row("SELECT MAX(version) FROM schema_migrations", [], |row| {
+            row.get(0)
+        })
+        .optional()?
+        .flatten();
+    Ok(version.unwrap_or(0))
+}
+
+/// Migrations newer than the version of the database
+pub fn pending(conn: &Connection) -> Result<Vec<&'static Migration>> {
+    let current = current_version(conn)?;
+    Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Applies the pending migrations, each in its own transaction, and returns them
+pub fn run(conn: &mut Connection) -> Result<Vec<&'static Migration>> {
+    ensure_table(conn)?;
+    let current = current_version(conn)?;
+    if current > latest_version() {
+        bail!(
+            "database schema version {} is newer than this build ({}), upgrade laravel-docs-mcp",
+            current,
+            latest_version()
+        );
+    }
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        // 其它进程可能同时打开了数据库, 在写事务里再确认一次
+        if current_version(&tx)? >= migration.version {
+            continue;
+        }
+        (migration.up)(&tx)
+            .with_context(|| format!("migration {} {}", migration.version, migration.name))?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            params![
+                migration.version,
+                migration.name,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
+        tx.commit()?;
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(current_version(&conn).unwrap(), 0);
+        assert_eq!(pending(&conn).unwrap().len(), MIGRATIONS.len());
+
+        let applied = run(&mut conn).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        assert!(pending(&conn).unwrap().is_empty());
+        // 已是最新版本时不再执行
+        assert!(run(&mut conn).unwrap().is_empty());
+
+        conn.execute(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, 'future', '')",
+            params![latest_version() + 1],
+        )
+        .unwrap();
+        assert!(run(&mut conn).is_err());
+    }
+}
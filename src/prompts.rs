@@ -0,0 +1,296 @@
+use anyhow::{Result, anyhow, bail};
+use rmcp::model::{JsonObject, Prompt, PromptArgument};
+use std::fmt::Write;
+
+/// Collection searched when a prompt is not given one
+pub const DEFAULT_PROMPT_COLLECTION: &str = "laravel_docs";
+
+/// Hits retrieved for each search of a prompt
+pub const PROMPT_HITS: usize = 6;
+
+/// A retrieved chunk quoted in a prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Citation {
+    /// Heading path of the chunk, or its source file when it has none
+    pub title: String,
+    pub url: Option<String>,
+    pub text: String,
+}
+
+/// One retrieval run by a prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptSearch {
+    pub query: String,
+    /// Docs version searched, `None` for the collection's current version
+    pub version: Option<String>,
+}
+
+/// Prompts advertised by the server, each filled with retrieved documentation
+#[derive(Debug, Clone, PartialEq)]
+pub enum LaravelPrompt {
+    ExplainFeature {
+        feature: String,
+        version: Option<String>,
+    },
+    UpgradeGuideDiff {
+        from: String,
+        to: String,
+        topic: Option<String>,
+    },
+    WriteMigration {
+        description: String,
+        version: Option<String>,
+    },
+}
+
+fn argument(name: &str, description: &str, required: bool) -> PromptArgument {
+    PromptArgument {
+        name: name.to_string(),
+        description: Some(description.to_string()),
+        required: Some(required),
+    }
+}
+
+/// Prompt definitions returned by `prompts/list`
+pub fn list() -> Vec<Prompt> {
+    let collection = argument(
+        "collection",
+        "Collection to retrieve from, defaults to laravel_docs",
+        false,
+    );
+    vec![
+        Prompt::new(
+            "explain_laravel_feature",
+            Some("Explain a Laravel feature from the documentation, with citations"),
+            Some(vec![
+                argument("feature", "Feature to explain, e.g. eager loading", true),
+                argument("version", "Docs version, e.g. 11.x", false),
+                collection.clone(),
+            ]),
+        ),
+        Prompt::new(
+            "upgrade_guide_diff",
+            Some("Summarize what changes between two Laravel versions, with citations"),
+            Some(vec![
+                argument("from_version", "Version upgraded from, e.g. 10.x", true),
+                argument("to_version", "Version upgraded to, e.g. 11.x", true),
+                argument(
+                    "topic",
+                    "Narrow the comparison to a topic, e.g. middleware",
+                    false,
+                ),
+                collection.clone(),
+            ]),
+        ),
+        Prompt::new(
+            "write_migration",
+            Some("Write a Laravel migration following the documented schema builder API"),
+            Some(vec![
+                argument(
+                    "description",
+                    "Table changes the migration should make",
+                    true,
+                ),
+                argument("version", "Docs version, e.g. 11.x", false),
+                collection,
+            ]),
+        ),
+    ]
+}
+
+/// String argument of a prompt request, empty strings count as missing
+fn string_arg(arguments: &JsonObject, name: &str) -> Option<String> {
+    arguments
+        .get(name)
+        .and_then(|v| v.as_str())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn required_arg(arguments: &JsonObject, name: &str) -> Result<String> {
+    string_arg(arguments, name).ok_or_else(|| anyhow!("missing required argument `{}`", name))
+}
+
+impl LaravelPrompt {
+    /// Parses a `prompts/get` request, returns the prompt and the collection it retrieves from
+    pub fn parse(name: &str, arguments: &JsonObject) -> Result<(Self, String)> {
+        let prompt = match name {
+            "explain_laravel_feature" => Self::ExplainFeature {
+                feature: required_arg(arguments, "feature")?,
+                version: string_arg(arguments, "version"),
+            },
+            "upgrade_guide_diff" => Self::UpgradeGuideDiff {
+                from: required_arg(arguments, "from_version")?,
+                to: required_arg(arguments, "to_version")?,
+                topic: string_arg(arguments, "topic"),
+            },
+            "write_migration" => Self::WriteMigration {
+                description: required_arg(arguments, "description")?,
+                version: string_arg(arguments, "version"),
+            },
+            _ => bail!("unknown prompt `{}`", name),
+        };
+        let collection = string_arg(arguments, "collection")
+            .unwrap_or_else(|| DEFAULT_PROMPT_COLLECTION.to_string());
+        Ok((prompt, collection))
+    }
+
+    /// Searches whose hits fill the prompt, in the order `render` expects them
+    pub fn searches(&self) -> Vec<PromptSearch> {
+        match self {
+            Self::ExplainFeature { feature, version } => vec![PromptSearch {
+                query: feature.clone(),
+                version: version.clone(),
+            }],
+            Self::UpgradeGuideDiff { from, to, topic } => {
+                let query = match topic {
+                    Some(topic) => format!("upgrade guide {}", topic),
+                    None => "upgrade guide breaking changes".to_string(),
+                };
+                // 两个版本各检索一次, 让模型对比差异
+                vec![
+                    PromptSearch {
+                        query: query.clone(),
+                        version: Some(from.clone()),
+                    },
+                    PromptSearch {
+                        query,
+                        version: Some(to.clone()),
+                    },
+                ]
+            }
+            Self::WriteMigration {
+                description,
+                version,
+            } => vec![
+                PromptSearch {
+                    query: format!("migration {}", description),
+                    version: version.clone(),
+                },
+                PromptSearch {
+                    query: "schema builder available column types".to_string(),
+                    version: version.clone(),
+                },
+            ],
+        }
+    }
+
+    /// Short description returned with the prompt
+    pub fn description(&self) -> String {
+        match self {
+            Self::ExplainFeature { feature, .. } => format!("Explain {}", feature),
+            Self::UpgradeGuideDiff { from, to, .. } => format!("Upgrade from {} to {}", from, to),
+            Self::WriteMigration { description, .. } => format!("Migration: {}", description),
+        }
+    }
+
+    /// Prompt text with the hits of every search numbered as citations
+    pub fn render(&self, results: &[Vec<Citation>]) -> String {
+        let mut text = match self {
+            Self::ExplainFeature { feature, version } => format!(
+                "Explain the Laravel feature \"{}\"{}. Cover what it is for, how to use it and the common pitfalls, with short code examples.",
+                feature,
+                version
+                    .as_ref()
+                    .map(|v| format!(" as of Laravel {}", v))
+                    .unwrap_or_default()
+            ),
+            Self::UpgradeGuideDiff { from, to, topic } => format!(
+                "Describe what changes when upgrading a Laravel application from {} to {}{}. List the breaking changes first, then the steps to upgrade.",
+                from,
+                to,
+                topic
+                    .as_ref()
+                    .map(|t| format!(", focusing on {}", t))
+                    .unwrap_or_default()
+            ),
+            Self::WriteMigration {
+                description,
+                version,
+            } => format!(
+                "Write a Laravel migration{} that does the following: {}. Use the schema builder methods shown in the documentation and include the down method.",
+                version
+                    .as_ref()
+                    .map(|v| format!(" for Laravel {}", v))
+                    .unwrap_or_default(),
+                description
+            ),
+        };
+        text.push_str(
+            " Base the answer only on the documentation excerpts below and cite them as [n].",
+        );
+
+        let labels = self.labels();
+        let mut number = 0;
+        for (i, citations) in results.iter().enumerate() {
+            if let Some(label) = labels.get(i) {
+                let _ = write!(text, "\n\n## {}", label);
+            }
+            if citations.is_empty() {
+                text.push_str("\n\nNo matching documentation was found.");
+            }
+            for citation in citations {
+                number += 1;
+                let _ = write!(text, "\n\n[{}] {}", number, citation.title);
+                if let Some(url) = &citation.url {
+                    let _ = write!(text, " ({})", url);
+                }
+                let _ = write!(text, "\n{}", citation.text);
+            }
+        }
+        text
+    }
+
+    /// Heading of each search's excerpts
+    fn labels(&self) -> Vec<String> {
+        match self {
+            Self::UpgradeGuideDiff { from, to, .. } => vec![
+                format!("Documentation for {}", from),
+                format!("Documentation for {}", to),
+            ],
+            _ => vec!["Documentation".to_string()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_upgrade_prompt() {
+        let arguments = json!({ "from_version": "10.x", "to_version": "11.x", "topic": " " });
+        let (prompt, collection) =
+            LaravelPrompt::parse("upgrade_guide_diff", arguments.as_object().unwrap()).unwrap();
+        assert_eq!(collection, DEFAULT_PROMPT_COLLECTION);
+        assert_eq!(
+            prompt.searches(),
+            vec![
+                PromptSearch {
+                    query: "upgrade guide breaking changes".to_string(),
+                    version: Some("10.x".to_string()),
+                },
+                PromptSearch {
+                    query: "upgrade guide breaking changes".to_string(),
+                    version: Some("11.x".to_string()),
+                },
+            ]
+        );
+
+        let citation = |title: &str| Citation {
+            title: title.to_string(),
+            url: Some(format!("https://laravel.com/docs/{}", title)),
+            text: format!("{} text", title),
+        };
+        let text = prompt.render(&[vec![citation("middleware")], vec![citation("routing")]]);
+        assert!(text.contains(
+            "## Documentation for 11.x\n\n[2] routing (https://laravel.com/docs/routing)\nrouting text"
+        ));
+
+        let missing = json!({ "from_version": "10.x" });
+        assert!(LaravelPrompt::parse("upgrade_guide_diff", missing.as_object().unwrap()).is_err());
+        assert!(LaravelPrompt::parse("unknown", &JsonObject::new()).is_err());
+        assert_eq!(list().len(), 3);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::vectorizer::SearchHit;
+use anyhow::{Result, anyhow, bail};
+use fastembed::{RerankInitOptions, TextRerank};
+use std::path::Path;
+
+/// Reranker used when a collection enables reranking without naming a model
+pub const DEFAULT_RERANKER: &str = "BGERerankerBase";
+
+/// Candidates fetched before reranking when the collection does not set `rerank_candidates`
+pub const RERANK_CANDIDATES: usize = 50;
+
+/// Scores query / document pairs, e.g. with a cross-encoder
+pub trait Reranker: Send + Sync {
+    /// Model name, for logs
+    fn model(&self) -> &str;
+
+    /// Relevance of every document to the query, in the order of the documents
+    fn score(&self, query: &str, documents: Vec<&str>) -> Result<Vec<f32>>;
+}
+
+/// fastembed cross-encoder
+pub struct FastReranker {
+    name: String,
+    reranker: TextRerank,
+}
+
+impl FastReranker {
+    /// Loads a supported fastembed reranker by name, e.g. `BGERerankerBase` or
+    /// `jinaai/jina-reranker-v1-turbo-en`
+    pub fn new(name: &str, cache_dir: &Path) -> Result<Self> {
+        let info = TextRerank::list_supported_models()
+            .into_iter()
+            .find(|info| format!("{:?}", info.model) == name || info.model_code == name)
+            .ok_or_else(|| anyhow!("unknown reranker model `{}`", name))?;
+        let reranker = TextRerank::try_new(
+            RerankInitOptions::new(info.model.clone())
+                .with_cache_dir(cache_dir.to_path_buf())
+                .with_show_download_progress(true),
+        )?;
+
+        Ok(Self {
+            name: format!("fastembed/{:?}", info.model),
+            reranker,
+        })
+    }
+}
+
+impl Reranker for FastReranker {
+    fn model(&self) -> &str {
+        &self.name
+    }
+
+    fn score(&self, query: &str, documents: Vec<&str>) -> Result<Vec<f32>> {
+        let len = documents.len();
+        // fastembed 返回按分数排序的结果, 这里按 index 还原成输入顺序
+        let mut scores = vec![0.0; len];
+        for result in self.reranker.rerank(query, documents, false, None)? {
+            *scores
+                .get_mut(result.index)
+                .ok_or_else(|| anyhow!("reranker returned index {} of {}", result.index, len))? =
+                result.score;
+        }
+        Ok(scores)
+    }
+}
+
+/// Text a hit is scored on, empty when the hit has no stored chunk
+fn hit_text(hit: &SearchHit) -> &str {
+    hit.chunk.as_ref().map_or("", |chunk| chunk.text.as_str())
+}
+
+/// Rescores hits against the query and returns the best `limit` of them,
+/// `score` of the returned hits is the reranker score
+pub fn rerank(
+    reranker: &dyn Reranker,
+    query: &str,
+    hits: Vec<SearchHit>,
+    limit: usize,
+) -> Result<Vec<SearchHit>> {
+    if hits.is_empty() {
+        return Ok(hits);
+    }
+
+    let texts = hits.iter().map(hit_text).collect::<Vec<_>>();
+    let scores = reranker.score(query, texts)?;
+    if scores.len() != hits.len() {
+        bail!(
+            "reranker returned {} scores for {} hits",
+            scores.len(),
+            hits.len()
+        );
+    }
+
+    let mut hits = hits
+        .into_iter()
+        .zip(scores)
+        .map(|(hit, score)| SearchHit {
+            score: score as f64,
+            ..hit
+        })
+        .collect::<Vec<_>>();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectorizer::StoredChunk;
+
+    /// Scores a document by how many query words it contains
+    struct WordOverlap;
+
+    impl Reranker for WordOverlap {
+        fn model(&self) -> &str {
+            "word-overlap"
+        }
+
+        fn score(&self, query: &str, documents: Vec<&str>) -> Result<Vec<f32>> {
+            Ok(documents
+                .iter()
+                .map(|d| query.split_whitespace().filter(|w| d.contains(w)).count() as f32)
+                .collect())
+        }
+    }
+
+    fn hit(rowid: i64, metadata: &str, distance: f64) -> SearchHit {
+        SearchHit {
+            rowid,
+            chunk: Some(StoredChunk::parse(metadata)),
+            distance: Some(distance),
+            score: crate::vectorizer::similarity(distance),
+        }
+    }
+
+    #[test]
+    fn test_rerank() {
+        let hits = vec![
+            hit(
+                1,
+                r#"{"id":"a-0","text":"Tangential words about models","source":"a.md"}"#,
+                0.1,
+            ),
+            hit(2, "Eager loading relationships with the with method", 0.4),
+            hit(
+                3,
+                r#"{"id":"c-0","text":"Eager loading avoids N+1 queries","source":"c.md"}"#,
+                0.6,
+            ),
+        ];
+
+        let reranked = rerank(&WordOverlap, "eager loading with", hits, 2).unwrap();
+        assert_eq!(
+            reranked.iter().map(|h| h.rowid).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(reranked[0].score, 2.0);
+        // 距离保留向量检索的值
+        assert_eq!(reranked[0].distance, Some(0.4));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::auth::Client;
+use crate::transport::{
+    SessionChannels, SessionId, SessionTransport, same_client, serve_sessions, session_id,
+};
+use axum::{
+    Extension, Json, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, Sse},
+    },
+    routing::{get, post},
+};
+use futures::{Stream, StreamExt};
+use rmcp::{RoleServer, Service, model::ClientJsonRpcMessage};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{RwLock, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+
+struct Session {
+    tx: mpsc::Sender<ClientJsonRpcMessage>,
+    /// Client that opened the event stream, only it may post to the session
+    client: Option<Arc<Client>>,
+}
+
+type Sessions = Arc<RwLock<HashMap<SessionId, Session>>>;
+
+#[derive(Clone)]
+struct App {
+    sessions: Sessions,
+    transport_tx: mpsc::UnboundedSender<SessionTransport>,
+    post_path: Arc<str>,
+    ct: CancellationToken,
+}
+
+/// SSE transport speaking the same protocol as rmcp's `SseServer`, but built
+/// as a router so that auth and other layers can wrap it
+pub struct SseServer {
+    router: Router,
+    transport_rx: mpsc::UnboundedReceiver<SessionTransport>,
+    ct: CancellationToken,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PostQuery {
+    session_id: String,
+}
+
+/// Ends the session once its event stream is dropped, e.g. when the client disconnects
+struct SessionGuard {
+    id: SessionId,
+    sessions: Sessions,
+    ct: CancellationToken,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.ct.cancel();
+        let sessions = self.sessions.clone();
+        let id = self.id.clone();
+        tokio::spawn(async move {
+            sessions.write().await.remove(&id);
+            tracing::info!(session_id = %id, "sse session closed");
+        });
+    }
+}
+
+async fn sse_handler(
+    State(app): State<App>,
+    client: Option<Extension<Arc<Client>>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, std::io::Error>>>, Response> {
+    let client = client.map(|Extension(c)| c);
+    let id = session_id();
+    let SessionChannels {
+        transport,
+        to_service,
+        from_service,
+    } = SessionChannels::new(app.ct.child_token(), client.clone());
+    let guard = SessionGuard {
+        id: id.clone(),
+        sessions: app.sessions.clone(),
+        ct: transport.ct(),
+    };
+    if app.transport_tx.send(transport).is_err() {
+        tracing::warn!("sse server is closed");
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "server is shutting down").into_response());
+    }
+    app.sessions.write().await.insert(
+        id.clone(),
+        Session {
+            tx: to_service,
+            client,
+        },
+    );
+    tracing::info!(session_id = %id, "sse connection");
+
+    let endpoint = Event::default()
+        .event("endpoint")
+        .data(format!("{}?sessionId={}", app.post_path, id));
+    let stream = futures::stream::once(async { Ok(endpoint) }).chain(
+        ReceiverStream::new(from_service).map(move |message| {
+            // 流存在期间会话保持打开
+            let _ = &guard;
+            serde_json::to_string(&message)
+                .map(|data| Event::default().event("message").data(data))
+                .map_err(std::io::Error::other)
+        }),
+    );
+    Ok(Sse::new(stream))
+}
+
+async fn post_handler(
+    State(app): State<App>,
+    client: Option<Extension<Arc<Client>>>,
+    Query(PostQuery { session_id }): Query<PostQuery>,
+    Json(message): Json<ClientJsonRpcMessage>,
+) -> StatusCode {
+    let client = client.map(|Extension(c)| c);
+    let tx = match app.sessions.read().await.get(session_id.as_str()) {
+        Some(session) if same_client(&session.client, &client) => session.tx.clone(),
+        Some(_) => return StatusCode::FORBIDDEN,
+        None => return StatusCode::NOT_FOUND,
+    };
+    if tx.send(message).await.is_err() {
+        tracing::warn!(session_id, "sse session is closed");
+        return StatusCode::GONE;
+    }
+    StatusCode::ACCEPTED
+}
+
+impl SseServer {
+    /// Opens event streams on `sse_path` and takes client messages on
+    /// `post_path`, sessions are cancelled together with `ct`
+    pub fn new(sse_path: &str, post_path: &str, ct: CancellationToken) -> Self {
+        let (transport_tx, transport_rx) = mpsc::unbounded_channel();
+        let app = App {
+            sessions: Default::default(),
+            transport_tx,
+            post_path: Arc::from(post_path),
+            ct: ct.clone(),
+        };
+        let router = Router::new()
+            .route(sse_path, get(sse_handler))
+            .route(post_path, post(post_handler))
+            .with_state(app);
+        Self {
+            router,
+            transport_rx,
+            ct,
+        }
+    }
+
+    /// Routes of both endpoints, to be served with `axum::serve`
+    pub fn router(&self) -> Router {
+        self.router.clone()
+    }
+
+    /// Builds a service for every new session, given the client that opened it
+    pub fn with_service<S, F>(self, service_provider: F) -> CancellationToken
+    where
+        S: Service<RoleServer>,
+        F: Fn(Option<Arc<Client>>) -> S + Send + 'static,
+    {
+        serve_sessions(self.transport_rx, service_provider);
+        self.ct
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::ServerHandler;
+    use serde_json::{Value, json};
+    use std::io::{BufRead, BufReader};
+
+    #[derive(Clone)]
+    struct Empty;
+
+    impl ServerHandler for Empty {}
+
+    /// Data of the next event of the stream
+    fn next_event(reader: &mut impl BufRead, name: &str) -> String {
+        let mut event = String::new();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            assert!(reader.read_line(&mut line).unwrap() > 0, "stream ended");
+            let line = line.trim_end();
+            if let Some(e) = line.strip_prefix("event:") {
+                event = e.trim().to_string();
+            } else if let Some(data) = line.strip_prefix("data:")
+                && event == name
+            {
+                return data.trim().to_string();
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sse_session() {
+        let ct = CancellationToken::new();
+        let server = SseServer::new("/sse", "/message", ct.clone());
+        let router = server.router();
+        server.with_service(|_| Empty);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let shutdown = ct.clone();
+        tokio::spawn(async move {
+            axum::serve(listener, router)
+                .with_graceful_shutdown(async move { shutdown.cancelled().await })
+                .await
+        });
+
+        tokio::task::spawn_blocking(move || {
+            let stream = ureq::get(&format!("{}/sse", base)).call().unwrap();
+            let mut reader = BufReader::new(stream.into_reader());
+            let endpoint = next_event(&mut reader, "endpoint");
+            assert!(endpoint.starts_with("/message?sessionId="));
+
+            let response = ureq::post(&format!("{}{}", base, endpoint))
+                .send_json(json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "method": "initialize",
+                    "params": {
+                        "protocolVersion": "2024-11-05",
+                        "capabilities": {},
+                        "clientInfo": { "name": "test", "version": "0" }
+                    }
+                }))
+                .unwrap();
+            assert_eq!(response.status(), 202);
+            let message: Value = serde_json::from_str(&next_event(&mut reader, "message")).unwrap();
+            assert_eq!(message["id"], 1);
+            assert!(message["result"]["serverInfo"].is_object());
+
+            let unknown = ureq::post(&format!("{}/message?sessionId=missing", base))
+                .send_json(json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }));
+            assert!(matches!(unknown, Err(ureq::Error::Status(404, _))));
+        })
+        .await
+        .unwrap();
+        ct.cancel();
+    }
+}
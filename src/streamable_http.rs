@@ -0,0 +1,493 @@
+use crate::auth::Client;
+use crate::transport::{
+    SessionChannels, SessionId, SessionTransport, same_client, serve_sessions, session_id,
+};
+use axum::{
+    Extension, Json, Router,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use rmcp::{
+    RoleServer, Service,
+    model::{ClientJsonRpcMessage, ClientRequest, JsonRpcRequest, RequestId, ServerJsonRpcMessage},
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+/// Header naming the session of a request, assigned by the server on `initialize`
+pub const SESSION_HEADER: &str = "mcp-session-id";
+
+/// Sessions without requests for this long are closed
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Sessions open at the same time, further `initialize` requests are refused
+pub const DEFAULT_MAX_SESSIONS: usize = 256;
+
+/// Replies the service has not sent yet, keyed by the id of the request waiting for them
+type Pending = Arc<Mutex<HashMap<RequestId, oneshot::Sender<ServerJsonRpcMessage>>>>;
+
+struct Session {
+    tx: mpsc::Sender<ClientJsonRpcMessage>,
+    pending: Pending,
+    ct: CancellationToken,
+    /// Client that sent `initialize`, only it may use the session
+    client: Option<Arc<Client>>,
+    last_seen: Mutex<Instant>,
+}
+
+impl Session {
+    fn touch(&self) {
+        if let Ok(mut last_seen) = self.last_seen.lock() {
+            *last_seen = Instant::now();
+        }
+    }
+
+    /// No request arrived for `timeout` and none is waiting for a reply
+    fn is_idle(&self, timeout: Duration) -> bool {
+        let waiting = self.pending.lock().is_ok_and(|p| !p.is_empty());
+        let last_seen = self.last_seen.lock().map_or(Instant::now(), |t| *t);
+        !waiting && last_seen.elapsed() >= timeout
+    }
+}
+
+/// Removes the reply slot of a request when its POST finishes or is dropped,
+/// e.g. because the client disconnected before the service replied
+struct PendingGuard {
+    pending: Pending,
+    id: RequestId,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+type Sessions = Arc<RwLock<HashMap<SessionId, Arc<Session>>>>;
+
+#[derive(Clone)]
+struct App {
+    sessions: Sessions,
+    transport_tx: mpsc::UnboundedSender<SessionTransport>,
+    ct: CancellationToken,
+    idle_timeout: Duration,
+    max_sessions: usize,
+}
+
+/// Streamable HTTP transport: every client message is POSTed to a single
+/// endpoint and the reply comes back as the JSON body of the same request.
+/// Server initiated streams (GET) are not offered
+pub struct StreamableHttpServer {
+    path: String,
+    app: App,
+    transport_rx: mpsc::UnboundedReceiver<SessionTransport>,
+    ct: CancellationToken,
+}
+
+fn is_initialize(message: &ClientJsonRpcMessage) -> bool {
+    matches!(
+        message,
+        ClientJsonRpcMessage::Request(JsonRpcRequest {
+            request: ClientRequest::InitializeRequest(_),
+            ..
+        })
+    )
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, message.to_string()).into_response()
+}
+
+impl App {
+    /// Closes the sessions idle for longer than the timeout
+    async fn expire_idle(&self) {
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|id, session| {
+            if !session.is_idle(self.idle_timeout) {
+                return true;
+            }
+            session.ct.cancel();
+            tracing::info!(session_id = %id, "session expired");
+            false
+        });
+    }
+
+    /// Registers a new session and hands its transport to the service loop
+    async fn create_session(
+        &self,
+        client: Option<Arc<Client>>,
+    ) -> Result<(SessionId, Arc<Session>), Response> {
+        self.expire_idle().await;
+        if self.sessions.read().await.len() >= self.max_sessions {
+            tracing::warn!(max = self.max_sessions, "too many sessions");
+            return Err(error(StatusCode::SERVICE_UNAVAILABLE, "too many sessions"));
+        }
+        let id = session_id();
+        let SessionChannels {
+            transport,
+            to_service,
+            mut from_service,
+        } = SessionChannels::new(self.ct.child_token(), client.clone());
+        let session = Arc::new(Session {
+            tx: to_service,
+            pending: Default::default(),
+            ct: transport.ct(),
+            client,
+            last_seen: Mutex::new(Instant::now()),
+        });
+        if self.transport_tx.send(transport).is_err() {
+            tracing::warn!("streamable http server is closed");
+            return Err(error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "server is shutting down",
+            ));
+        }
+        self.sessions
+            .write()
+            .await
+            .insert(id.clone(), session.clone());
+
+        // 把服务的回复转交给等待它的 POST 请求, 服务结束后清理会话
+        let pending = session.pending.clone();
+        let sessions = self.sessions.clone();
+        let session_id = id.clone();
+        tokio::spawn(async move {
+            while let Some(message) = from_service.recv().await {
+                let id = match &message {
+                    ServerJsonRpcMessage::Response(response) => response.id.clone(),
+                    ServerJsonRpcMessage::Error(error) => error.id.clone(),
+                    _ => {
+                        tracing::debug!(%session_id, "dropping server initiated message");
+                        continue;
+                    }
+                };
+                let waiting = pending.lock().ok().and_then(|mut p| p.remove(&id));
+                if let Some(tx) = waiting {
+                    let _ = tx.send(message);
+                }
+            }
+            sessions.write().await.remove(&session_id);
+            tracing::info!(%session_id, "session closed");
+        });
+
+        tracing::info!(session_id = %id, "session created");
+        Ok((id, session))
+    }
+
+    /// Session named by the request headers, when it belongs to the caller
+    async fn session(
+        &self,
+        headers: &HeaderMap,
+        client: &Option<Arc<Client>>,
+    ) -> Result<Option<(SessionId, Arc<Session>)>, Response> {
+        let Some(id) = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()) else {
+            return Ok(None);
+        };
+        match self.sessions.read().await.get(id) {
+            Some(session) if same_client(&session.client, client) => {
+                session.touch();
+                Ok(Some((Arc::from(id), session.clone())))
+            }
+            Some(_) => Err(error(
+                StatusCode::FORBIDDEN,
+                "session belongs to another client",
+            )),
+            None => Err(error(StatusCode::NOT_FOUND, "unknown session")),
+        }
+    }
+}
+
+async fn post_handler(
+    State(app): State<App>,
+    client: Option<Extension<Arc<Client>>>,
+    headers: HeaderMap,
+    Json(message): Json<ClientJsonRpcMessage>,
+) -> Response {
+    let client = client.map(|Extension(c)| c);
+    let (session_id, session) = match app.session(&headers, &client).await {
+        Ok(Some(found)) => found,
+        Ok(None) if is_initialize(&message) => match app.create_session(client).await {
+            Ok(created) => created,
+            Err(response) => return response,
+        },
+        Ok(None) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "missing Mcp-Session-Id header, send initialize first",
+            );
+        }
+        Err(response) => return response,
+    };
+
+    let reply = match &message {
+        ClientJsonRpcMessage::Request(request) => {
+            let (tx, rx) = oneshot::channel();
+            if let Ok(mut pending) = session.pending.lock() {
+                pending.insert(request.id.clone(), tx);
+            }
+            let guard = PendingGuard {
+                pending: session.pending.clone(),
+                id: request.id.clone(),
+            };
+            Some((rx, guard))
+        }
+        _ => None,
+    };
+    if session.tx.send(message).await.is_err() {
+        app.sessions.write().await.remove(&session_id);
+        return error(StatusCode::NOT_FOUND, "session is closed");
+    }
+
+    let mut response = match reply {
+        Some((rx, _guard)) => match rx.await {
+            Ok(message) => Json(message).into_response(),
+            Err(_) => error(StatusCode::NOT_FOUND, "session closed before replying"),
+        },
+        None => StatusCode::ACCEPTED.into_response(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+    response
+}
+
+async fn delete_handler(
+    State(app): State<App>,
+    client: Option<Extension<Arc<Client>>>,
+    headers: HeaderMap,
+) -> Response {
+    let client = client.map(|Extension(c)| c);
+    match app.session(&headers, &client).await {
+        Ok(Some((id, session))) => {
+            app.sessions.write().await.remove(&id);
+            session.ct.cancel();
+            tracing::info!(session_id = %id, "session deleted by client");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => StatusCode::BAD_REQUEST.into_response(),
+        Err(response) => response,
+    }
+}
+
+impl StreamableHttpServer {
+    /// Serves the transport on `path`, sessions are cancelled together with `ct`
+    pub fn new(path: &str, ct: CancellationToken) -> Self {
+        let (transport_tx, transport_rx) = mpsc::unbounded_channel();
+        let app = App {
+            sessions: Default::default(),
+            transport_tx,
+            ct: ct.clone(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+        };
+        Self {
+            path: path.to_string(),
+            app,
+            transport_rx,
+            ct,
+        }
+    }
+
+    /// Close sessions that received no request for `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.app.idle_timeout = timeout;
+        self
+    }
+
+    /// Refuse new sessions while `max` are open
+    pub fn with_max_sessions(mut self, max: usize) -> Self {
+        self.app.max_sessions = max;
+        self
+    }
+
+    /// Routes of the endpoint, to be served with `axum::serve`
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route(
+                &self.path,
+                post(post_handler)
+                    .delete(delete_handler)
+                    .get(|| async { StatusCode::METHOD_NOT_ALLOWED }),
+            )
+            .with_state(self.app.clone())
+    }
+
+    /// Builds a service for every new session, given the client that opened it
+    pub fn with_service<S, F>(self, service_provider: F) -> CancellationToken
+    where
+        S: Service<RoleServer>,
+        F: Fn(Option<Arc<Client>>) -> S + Send + 'static,
+    {
+        serve_sessions(self.transport_rx, service_provider);
+
+        // 新会话创建时也会清理, 这里定期清理以便没有新会话时也能释放资源
+        let app = self.app;
+        let ct = self.ct.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(app.idle_timeout.max(Duration::from_secs(4)) / 4);
+            loop {
+                tokio::select! {
+                    _ = ct.cancelled() => break,
+                    _ = interval.tick() => app.expire_idle().await,
+                }
+            }
+        });
+        self.ct
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::ServerHandler;
+    use serde_json::{Value, json};
+
+    #[derive(Clone)]
+    struct Empty;
+
+    impl ServerHandler for Empty {}
+
+    /// Sends a message, error statuses are returned as responses too
+    fn post(url: &str, session: Option<&str>, body: Value) -> ureq::Response {
+        let mut request = ureq::post(url);
+        if let Some(session) = session {
+            request = request.set(SESSION_HEADER, session);
+        }
+        match request.send_json(body) {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => panic!("request failed: {}", e),
+        }
+    }
+
+    /// Serves the endpoint on a free port and returns its url
+    async fn spawn(server: StreamableHttpServer) -> String {
+        let router = server.router();
+        let ct = server.with_service(|_| Empty);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mcp", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, router)
+                .with_graceful_shutdown(async move { ct.cancelled().await })
+                .await
+        });
+        url
+    }
+
+    fn initialize(url: &str) -> ureq::Response {
+        post(
+            url,
+            None,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": {
+                    "protocolVersion": "2024-11-05",
+                    "capabilities": {},
+                    "clientInfo": { "name": "test", "version": "0" }
+                }
+            }),
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_streamable_http_session() {
+        let ct = CancellationToken::new();
+        let url = spawn(StreamableHttpServer::new("/mcp", ct.clone())).await;
+
+        tokio::task::spawn_blocking(move || {
+            // 没有会话时只接受 initialize
+            assert_eq!(
+                post(
+                    &url,
+                    None,
+                    json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" }),
+                )
+                .status(),
+                400
+            );
+
+            let response = initialize(&url);
+            let session = response.header(SESSION_HEADER).unwrap().to_string();
+            let body: Value = response.into_json().unwrap();
+            assert_eq!(body["id"], 1);
+            assert!(body["result"]["serverInfo"].is_object());
+
+            let response = post(
+                &url,
+                Some(&session),
+                json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+            );
+            assert_eq!(response.status(), 202);
+
+            let body: Value = post(
+                &url,
+                Some(&session),
+                json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }),
+            )
+            .into_json()
+            .unwrap();
+            assert_eq!(body["id"], 2);
+
+            // DELETE 结束会话
+            let response = ureq::delete(&url)
+                .set(SESSION_HEADER, &session)
+                .call()
+                .unwrap();
+            assert_eq!(response.status(), 204);
+            assert_eq!(
+                post(
+                    &url,
+                    Some(&session),
+                    json!({ "jsonrpc": "2.0", "id": 3, "method": "ping" }),
+                )
+                .status(),
+                404
+            );
+        })
+        .await
+        .unwrap();
+        ct.cancel();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_session_limits() {
+        let ct = CancellationToken::new();
+        let server = StreamableHttpServer::new("/mcp", ct.clone())
+            .with_max_sessions(1)
+            .with_idle_timeout(Duration::from_millis(300));
+        let url = spawn(server).await;
+
+        tokio::task::spawn_blocking(move || {
+            let first = initialize(&url);
+            assert_eq!(first.status(), 200);
+            let first = first.header(SESSION_HEADER).unwrap().to_string();
+            // 会话数已满
+            assert_eq!(initialize(&url).status(), 503);
+
+            // 空闲超时的会话被关闭, 腾出位置
+            std::thread::sleep(Duration::from_millis(400));
+            assert_eq!(initialize(&url).status(), 200);
+            assert_eq!(
+                post(
+                    &url,
+                    Some(&first),
+                    json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }),
+                )
+                .status(),
+                404
+            );
+        })
+        .await
+        .unwrap();
+        ct.cancel();
+    }
+}
@@ -0,0 +1,205 @@
+use crate::config::RepoConfig;
+use anyhow::{Context, Result, anyhow};
+use git2::{FetchOptions, Oid, Repository, build::CheckoutBuilder, build::RepoBuilder};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Working copy of a configured docs repository
+pub struct RepoCheckout {
+    repo: Repository,
+    dir: PathBuf,
+    config: RepoConfig,
+}
+
+impl RepoCheckout {
+    /// Clones the repository under `workdir` on first use, fetches it afterwards
+    pub fn open(config: &RepoConfig, workdir: &Path) -> Result<Self> {
+        let name = match &config.version {
+            Some(version) => format!("{}@{}", config.collection, version),
+            None => config.collection.clone(),
+        };
+        let dir = workdir.join(name);
+
+        let repo = if dir.join(".git").exists() {
+            let repo = Repository::open(&dir)
+                .with_context(|| format!("Failed to open {}", dir.display()))?;
+            repo.remote_set_url("origin", &config.url)?;
+            repo.find_remote("origin")?
+                .fetch(
+                    &[
+                        "+refs/heads/*:refs/remotes/origin/*",
+                        "+refs/tags/*:refs/tags/*",
+                    ],
+                    Some(&mut FetchOptions::new()),
+                    None,
+                )
+                .with_context(|| format!("Failed to fetch {}", config.url))?;
+            repo
+        } else {
+            std::fs::create_dir_all(workdir)?;
+            RepoBuilder::new()
+                .clone(&config.url, &dir)
+                .with_context(|| format!("Failed to clone {}", config.url))?
+        };
+
+        Ok(Self {
+            repo,
+            dir,
+            config: config.clone(),
+        })
+    }
+
+    /// Directory holding the markdown files of the checkout
+    pub fn docs_dir(&self) -> PathBuf {
+        match &self.config.path {
+            Some(path) => self.dir.join(path),
+            None => self.dir.clone(),
+        }
+    }
+
+    /// Checks out the configured branch or tag and returns its commit
+    pub fn checkout(&self) -> Result<Oid> {
+        let reference = match (&self.config.branch, &self.config.tag) {
+            (Some(branch), _) => format!("refs/remotes/origin/{}", branch),
+            (None, Some(tag)) => format!("refs/tags/{}", tag),
+            (None, None) => return Err(anyhow!("repo `{}` has no branch or tag", self.config.url)),
+        };
+        let commit = self
+            .repo
+            .find_reference(&reference)
+            .with_context(|| format!("`{}` not found in {}", reference, self.config.url))?
+            .peel_to_commit()?;
+
+        self.repo.checkout_tree(
+            commit.as_object(),
+            Some(CheckoutBuilder::new().force().remove_untracked(true)),
+        )?;
+        self.repo.set_head_detached(commit.id())?;
+        Ok(commit.id())
+    }
+
+    /// Markdown files under the docs directory that differ between two commits,
+    /// `None` when `from` is not in the repository anymore (e.g. after a force push)
+    pub fn changed_files(&self, from: &str, to: Oid) -> Result<Option<Vec<PathBuf>>> {
+        let old = match Oid::from_str(from).and_then(|oid| self.repo.find_commit(oid)) {
+            Ok(commit) => commit.tree()?,
+            Err(_) => return Ok(None),
+        };
+        let new = self.repo.find_commit(to)?.tree()?;
+        let diff = self.repo.diff_tree_to_tree(Some(&old), Some(&new), None)?;
+
+        let prefix = self.config.path.clone().unwrap_or_default();
+        // 改名的文件旧路径需要删除, 新路径需要导入, 两边都收集
+        let mut files = BTreeSet::new();
+        for delta in diff.deltas() {
+            for path in [delta.old_file().path(), delta.new_file().path()]
+                .into_iter()
+                .flatten()
+            {
+                if path.starts_with(&prefix) && path.extension().is_some_and(|ext| ext == "md") {
+                    files.insert(self.dir.join(path));
+                }
+            }
+        }
+        Ok(Some(files.into_iter().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    fn commit_files(repo: &Repository, files: &[(&str, Option<&str>)]) -> Oid {
+        let root = repo.workdir().unwrap();
+        let mut index = repo.index().unwrap();
+        for (path, content) in files {
+            match content {
+                Some(content) => {
+                    std::fs::write(root.join(path), content).unwrap();
+                    index.add_path(Path::new(path)).unwrap();
+                }
+                None => {
+                    std::fs::remove_file(root.join(path)).unwrap();
+                    index.remove_path(Path::new(path)).unwrap();
+                }
+            }
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("docs", "docs@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "update docs",
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sync_changed_files() {
+        let root = std::env::temp_dir().join(format!("test_sync_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let upstream_dir = root.join("upstream");
+        let upstream = Repository::init(&upstream_dir).unwrap();
+        let first = commit_files(
+            &upstream,
+            &[
+                ("routing.md", Some("# Routing")),
+                ("eloquent.md", Some("# Eloquent")),
+                ("README.txt", Some("readme")),
+            ],
+        );
+        let branch = upstream.head().unwrap().shorthand().unwrap().to_string();
+
+        let config = RepoConfig {
+            collection: "laravel_docs".to_string(),
+            url: format!("file://{}", upstream_dir.display()),
+            branch: Some(branch),
+            tag: None,
+            path: None,
+            version: Some("12.x".to_string()),
+            chunk_size: 400,
+            chunk_overlap: 20,
+        };
+        let workdir = root.join("work");
+        let checkout = RepoCheckout::open(&config, &workdir).unwrap();
+        assert_eq!(checkout.checkout().unwrap(), first);
+        assert_eq!(checkout.docs_dir(), workdir.join("laravel_docs@12.x"));
+        assert!(checkout.docs_dir().join("routing.md").exists());
+
+        let second = commit_files(
+            &upstream,
+            &[
+                ("routing.md", Some("# Routing\n\nNew section")),
+                ("eloquent.md", None),
+                ("README.txt", Some("changed readme")),
+            ],
+        );
+
+        // 第二次打开走 fetch, 只返回变化的 markdown 文件
+        let checkout = RepoCheckout::open(&config, &workdir).unwrap();
+        assert_eq!(checkout.checkout().unwrap(), second);
+        let dir = checkout.docs_dir();
+        assert_eq!(
+            checkout
+                .changed_files(&first.to_string(), second)
+                .unwrap()
+                .unwrap(),
+            vec![dir.join("eloquent.md"), dir.join("routing.md")]
+        );
+        assert!(!dir.join("eloquent.md").exists());
+        assert!(
+            checkout
+                .changed_files("0000000000000000000000000000000000000001", second)
+                .unwrap()
+                .is_none()
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::auth::Client;
+use futures::SinkExt;
+use rmcp::{
+    RoleServer, Service, ServiceExt,
+    model::{ClientJsonRpcMessage, ServerJsonRpcMessage},
+};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::{CancellationToken, PollSender};
+
+pub(crate) type SessionId = Arc<str>;
+
+pub(crate) fn session_id() -> SessionId {
+    Arc::from(format!("{:032x}", rand::random::<u128>()))
+}
+
+/// Transport of one session, handed to the service built for it
+pub struct SessionTransport {
+    sink: PollSender<ServerJsonRpcMessage>,
+    stream: ReceiverStream<ClientJsonRpcMessage>,
+    ct: CancellationToken,
+    client: Option<Arc<Client>>,
+}
+
+impl SessionTransport {
+    /// Token cancelling the session
+    pub(crate) fn ct(&self) -> CancellationToken {
+        self.ct.clone()
+    }
+}
+
+/// Ends of a new session kept by the HTTP side: where client messages are
+/// sent and where the service's messages come out
+pub(crate) struct SessionChannels {
+    pub transport: SessionTransport,
+    pub to_service: mpsc::Sender<ClientJsonRpcMessage>,
+    pub from_service: mpsc::Receiver<ServerJsonRpcMessage>,
+}
+
+impl SessionChannels {
+    pub fn new(ct: CancellationToken, client: Option<Arc<Client>>) -> Self {
+        let (to_service, from_client) = mpsc::channel(64);
+        let (to_client, from_service) = mpsc::channel(64);
+        Self {
+            transport: SessionTransport {
+                sink: PollSender::new(to_client),
+                stream: ReceiverStream::new(from_client),
+                ct,
+                client,
+            },
+            to_service,
+            from_service,
+        }
+    }
+}
+
+/// Builds a service for every session sent on `transports`, the provider gets
+/// the client that opened the session when auth is on
+pub(crate) fn serve_sessions<S, F>(
+    mut transports: mpsc::UnboundedReceiver<SessionTransport>,
+    service_provider: F,
+) where
+    S: Service<RoleServer>,
+    F: Fn(Option<Arc<Client>>) -> S + Send + 'static,
+{
+    tokio::spawn(async move {
+        while let Some(transport) = transports.recv().await {
+            let SessionTransport {
+                sink,
+                stream,
+                ct,
+                client,
+            } = transport;
+            let service = service_provider(client);
+            tokio::spawn(async move {
+                let sink = sink.sink_map_err(std::io::Error::other);
+                match service.serve_with_ct((sink, stream), ct).await {
+                    Ok(server) => {
+                        let _ = server.waiting().await;
+                    }
+                    Err(e) => tracing::error!(error = %e, "session failed to start"),
+                }
+            });
+        }
+    });
+}
+
+/// Whether a request may use a session opened by `owner`
+pub(crate) fn same_client(owner: &Option<Arc<Client>>, caller: &Option<Arc<Client>>) -> bool {
+    owner.as_ref().map(|c| &c.name) == caller.as_ref().map(|c| &c.name)
+}
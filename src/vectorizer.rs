@@ -1,12 +1,49 @@
-use anyhow::{Result, anyhow};
+use crate::chunker::{ChunkSettings, DEFAULT_CHUNK_OVERLAP, TextChunk, TextChunker, stitch_chunks};
+use crate::collection::CollectionName;
+use crate::embedder::Embedder;
+use crate::metrics;
+use crate::migrations::{self, Migration};
+use crate::reranker::{self, Reranker};
+use anyhow::{Result, anyhow, bail};
 use bytemuck::cast_slice;
-use fastembed::TextEmbedding;
-use rusqlite::{Connection, ffi::sqlite3_auto_extension, params};
+use rusqlite::{Connection, OptionalExtension, ffi::sqlite3_auto_extension, params};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{HashMap, HashSet},
+    fmt,
     path::Path,
+    str::FromStr,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
+/// Constant of reciprocal rank fusion, damps the weight of the top ranks
+const RRF_K: f64 = 60.0;
+/// Candidates fetched from each ranking before they are fused
+const HYBRID_CANDIDATES: usize = 50;
+
+/// Columns of `{collection}_metadata` besides `id`, in the order of [`StoredChunk::values`]
+const METADATA_COLUMNS: [&str; 9] = [
+    "chunk_id",
+    "source",
+    "section",
+    "anchor",
+    "position",
+    "text",
+    "token_count",
+    "version",
+    "content_hash",
+];
+
+/// Metadata columns prefixed with a table alias, for selects joining the metadata table
+fn metadata_columns(alias: &str) -> String {
+    METADATA_COLUMNS
+        .iter()
+        .map(|column| format!("{}.{}", alias, column))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub struct VectorParams {
     dimension: u32,
 }
@@ -17,43 +54,1415 @@ impl VectorParams {
     }
 }
 
+/// One row of the `{collection}_manifest` table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// md5 of the source file content when it was last indexed
+    pub content_hash: String,
+    /// Rowids the source file produced in the collection
+    pub rowids: Vec<i64>,
+}
+
+/// A source page of a collection, one per docs version and source file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageEntry {
+    /// Docs version the page was ingested as
+    pub version: Option<String>,
+    /// Source file path the page was ingested from
+    pub source: String,
+    /// Path of the page relative to the docs directory, the file name for
+    /// pages ingested before the original markdown was stored
+    pub path: String,
+    /// Length of the stored markdown in bytes, `None` when it is rebuilt from chunks
+    pub size: Option<usize>,
+}
+
+/// A chunk as stored in the columns of `{collection}_metadata`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoredChunk {
+    /// Id given by the chunker, `{file}-{position}`, `None` for plain text rows
+    pub chunk_id: Option<String>,
+    /// Source file path the chunk was cut from
+    pub source: Option<String>,
+    /// Heading path the chunk sits under
+    pub section: Option<String>,
+    /// Anchor slug of the nearest heading
+    pub anchor: Option<String>,
+    /// Index of the chunk in its source file
+    pub position: Option<i64>,
+    pub text: String,
+    /// Whitespace separated words of the text
+    pub token_count: i64,
+    /// Docs version the chunk was ingested as
+    pub version: Option<String>,
+    /// md5 of the text
+    pub content_hash: String,
+}
+
+/// Fields of a json chunk, all optional so partial objects keep what they have
+#[derive(Deserialize)]
+struct JsonChunk {
+    id: Option<String>,
+    text: Option<String>,
+    source: Option<String>,
+    section: Option<String>,
+    anchor: Option<String>,
+    version: Option<String>,
+}
+
+/// Index of a chunk in its file, the suffix of its `{file}-{position}` id
+fn chunk_position(id: &str) -> Option<i64> {
+    id.rsplit_once('-').and_then(|(_, i)| i.parse().ok())
+}
+
+impl StoredChunk {
+    /// Reads the metadata handed to the storage layer, a chunk serialized like
+    /// the lines of the ingest jsonl or plain text. Json without `text` keeps
+    /// the whole line as text
+    pub fn parse(metadata: &str) -> Self {
+        let Ok(json) = serde_json::from_str::<JsonChunk>(metadata) else {
+            return Self::plain(metadata);
+        };
+        Self {
+            position: json.id.as_deref().and_then(chunk_position),
+            chunk_id: json.id,
+            source: json.source,
+            section: json.section.filter(|s| !s.is_empty()),
+            anchor: json.anchor,
+            version: json.version,
+            ..Self::plain(json.text.as_deref().unwrap_or(metadata))
+        }
+    }
+
+    /// Text the chunk is embedded as: its heading path followed by the text,
+    /// without the json keys and ids of the metadata line
+    pub fn embedding_text(&self) -> String {
+        match &self.section {
+            Some(section) => format!("{}\n\n{}", section, self.text),
+            None => self.text.clone(),
+        }
+    }
+
+    fn plain(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            token_count: text.split_whitespace().count() as i64,
+            content_hash: TextChunker::content_hash(text),
+            ..Default::default()
+        }
+    }
+
+    /// The chunk as produced by the chunker, `None` for plain text rows
+    pub fn into_text_chunk(self) -> Option<TextChunk> {
+        Some(TextChunk {
+            id: self.chunk_id?,
+            text: self.text,
+            source: self.source?,
+            section: self.section.unwrap_or_default(),
+            anchor: self.anchor,
+            version: self.version,
+        })
+    }
+
+    /// Values bound to [`METADATA_COLUMNS`]
+    fn values(&self) -> Vec<rusqlite::types::Value> {
+        vec![
+            self.chunk_id.clone().into(),
+            self.source.clone().into(),
+            self.section.clone().into(),
+            self.anchor.clone().into(),
+            self.position.into(),
+            self.text.clone().into(),
+            self.token_count.into(),
+            self.version.clone().into(),
+            self.content_hash.clone().into(),
+        ]
+    }
+
+    /// Reads [`METADATA_COLUMNS`] selected from column `first` on, `None`
+    /// when a LEFT JOIN found no metadata row
+    fn from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Option<Self>> {
+        let Some(text) = row.get::<_, Option<String>>(first + 5)? else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            chunk_id: row.get(first)?,
+            source: row.get(first + 1)?,
+            section: row.get(first + 2)?,
+            anchor: row.get(first + 3)?,
+            position: row.get(first + 4)?,
+            text,
+            token_count: row.get(first + 6)?,
+            version: row.get(first + 7)?,
+            content_hash: row.get(first + 8)?,
+        }))
+    }
+}
+
+impl From<TextChunk> for StoredChunk {
+    fn from(chunk: TextChunk) -> Self {
+        Self {
+            position: chunk_position(&chunk.id),
+            chunk_id: Some(chunk.id),
+            source: Some(chunk.source),
+            section: Some(chunk.section).filter(|s| !s.is_empty()),
+            anchor: chunk.anchor,
+            version: chunk.version,
+            ..Self::plain(&chunk.text)
+        }
+    }
+}
+
+/// A single hit returned by a vector, keyword or hybrid search
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    /// Rowid of the chunk in the collection
+    pub rowid: i64,
+    /// Chunk stored next to the embedding
+    pub chunk: Option<StoredChunk>,
+    /// L2 distance reported by sqlite-vec, lower is closer, `None` for keyword only hits
+    pub distance: Option<f64>,
+    /// Ranking score, higher is better: cosine similarity for vector hits,
+    /// negated BM25 for keyword hits and the fused score for hybrid hits
+    pub score: f64,
+}
+
+/// Chunk returned by a logged search
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedHit {
+    pub id: String,
+    pub score: f64,
+}
+
+/// A search as stored in `query_log`
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryLogEntry {
+    pub collection: String,
+    pub query: String,
+    pub mode: String,
+    pub version: Option<String>,
+    /// Authenticated client that ran the search
+    pub client: Option<String>,
+    /// Hits in the order they were returned
+    pub hits: Vec<LoggedHit>,
+    pub latency_ms: f64,
+}
+
+/// Cosine similarity derived from the L2 distance, valid for normalized embeddings
+pub fn similarity(distance: f64) -> f64 {
+    1.0 - distance * distance / 2.0
+}
+
+/// Restricts a search to part of a collection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilter {
+    /// Only chunks ingested with this docs version
+    pub version: Option<String>,
+    /// Only pages whose path relative to the docs directory starts with one of
+    /// these, e.g. `eloquent` for every Eloquent page
+    pub source_prefixes: Vec<String>,
+    /// Only chunks whose heading path contains this, ignoring ASCII case
+    pub section: Option<String>,
+}
+
+/// Escapes the LIKE wildcards of a user value, for `LIKE ? ESCAPE '\'`
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+impl SearchFilter {
+    pub fn with_version(mut self, version: Option<String>) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn with_source_prefixes(mut self, prefixes: Vec<String>) -> Self {
+        self.source_prefixes = prefixes;
+        self
+    }
+
+    pub fn with_section(mut self, section: Option<String>) -> Self {
+        self.section = section;
+        self
+    }
+
+    /// `AND {column} IN (...)` restricting rowids to the filter, numbered from `?{first}`.
+    /// Inside the vec0 query the rowids are restricted before the KNN, so the
+    /// k hits all match the filter
+    fn rowid_clause(
+        &self,
+        collection: &CollectionName,
+        column: &str,
+        first: usize,
+    ) -> (String, Vec<rusqlite::types::Value>) {
+        let mut conditions = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(version) = &self.version {
+            conditions.push(format!("version = ?{}", first + values.len()));
+            values.push(version.clone().into());
+        }
+        if !self.source_prefixes.is_empty() {
+            let mut paths = Vec::new();
+            let mut sources = Vec::new();
+            for prefix in &self.source_prefixes {
+                let prefix = escape_like(prefix.trim_start_matches('/'));
+                paths.push(format!("path LIKE ?{} ESCAPE '\\'", first + values.len()));
+                values.push(format!("{}%", prefix).into());
+                sources.push(format!("source LIKE ?{} ESCAPE '\\'", first + values.len()));
+                values.push(format!("%/{}%", prefix).into());
+            }
+            // 页面表出现之前导入的文件没有页面行, 只能按绝对路径中的目录分隔符匹配
+            let pages = collection.table_for("pages");
+            conditions.push(format!(
+                "(source IN (SELECT source FROM {pages} WHERE {}) \
+                 OR (source NOT IN (SELECT source FROM {pages}) AND ({})))",
+                paths.join(" OR "),
+                sources.join(" OR ")
+            ));
+        }
+        if let Some(section) = &self.section {
+            conditions.push(format!(
+                "section LIKE ?{} ESCAPE '\\'",
+                first + values.len()
+            ));
+            values.push(format!("%{}%", escape_like(section)).into());
+        }
+        if conditions.is_empty() {
+            return (String::new(), values);
+        }
+        (
+            format!(
+                " AND {} IN (SELECT id FROM {} WHERE {})",
+                column,
+                collection.table_for("metadata"),
+                conditions.join(" AND ")
+            ),
+            values,
+        )
+    }
+}
+
+/// Which ranking a search uses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// KNN over the embeddings
+    #[default]
+    Vector,
+    /// BM25 over the FTS5 index, best for exact API names
+    Keyword,
+    /// Both rankings fused with reciprocal rank fusion
+    Hybrid,
+}
+
+impl FromStr for SearchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "vector" => Ok(Self::Vector),
+            "keyword" => Ok(Self::Keyword),
+            "hybrid" => Ok(Self::Hybrid),
+            _ => Err(anyhow!(
+                "unknown search mode `{}`, expected vector, keyword or hybrid",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Vector => "vector",
+            Self::Keyword => "keyword",
+            Self::Hybrid => "hybrid",
+        })
+    }
+}
+
+/// Fuses several rankings with reciprocal rank fusion, `score = Σ 1 / (k + rank)`
+pub fn reciprocal_rank_fusion(rankings: Vec<Vec<SearchHit>>, limit: usize) -> Vec<SearchHit> {
+    let mut fused: Vec<SearchHit> = Vec::new();
+    let mut positions: HashMap<i64, usize> = HashMap::new();
+
+    for ranking in rankings {
+        for (rank, hit) in ranking.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f64 + 1.0);
+            match positions.get(&hit.rowid) {
+                Some(&i) => {
+                    fused[i].score += score;
+                    fused[i].distance = fused[i].distance.or(hit.distance);
+                }
+                None => {
+                    positions.insert(hit.rowid, fused.len());
+                    fused.push(SearchHit { score, ..hit });
+                }
+            }
+        }
+    }
+
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused.truncate(limit);
+    fused
+}
+
+/// Turns free text into an FTS5 query matching any of its terms, so
+/// punctuation like `schedule:work` cannot break the MATCH syntax
+pub fn fts_query(text: &str) -> Option<String> {
+    let mut seen = HashSet::new();
+    let terms = text
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|term| !term.is_empty() && seen.insert(term.to_lowercase()))
+        .map(|term| format!("\"{}\"", term))
+        .collect::<Vec<_>>();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" OR "))
+    }
+}
+
+fn embedding_bytes(embedding: &[f32]) -> &[u8] {
+    cast_slice(embedding)
+}
+
 /// Vectorizer for text embedding using sqlite-vec
 pub struct SqliteVector {
     conn: Connection,
 }
 
 impl SqliteVector {
-    /// Creates a new SqliteVector with the specified database path
+    /// Creates a new SqliteVector with the specified database path, bringing
+    /// its schema up to date
     pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        let mut db = Self::open(db_path)?;
+        for migration in db.migrate()? {
+            log::info!("Applied migration {} {}", migration.version, migration.name);
+        }
+        Ok(db)
+    }
+
+    /// Opens the database as it is, without applying the pending migrations
+    pub fn open<P: AsRef<Path>>(db_path: P) -> Result<Self> {
         // Register the sqlite-vec extension
         unsafe {
-            sqlite3_auto_extension(Some(std::mem::transmute(
-                sqlite_vec::sqlite3_vec_init as *const (),
+            sqlite3_auto_extension(Some(std::mem::transmute::<
+                *const (),
+                unsafe extern "C" fn(
+                    *mut rusqlite::ffi::sqlite3,
+                    *mut *mut std::os::raw::c_char,
+                    *const rusqlite::ffi::sqlite3_api_routines,
+                ) -> std::os::raw::c_int,
+            >(
+                sqlite_vec::sqlite3_vec_init as *const ()
             )));
         }
 
-        // Open the database connection
-        let conn = Connection::open(db_path)?;
+        Ok(Self {
+            conn: Connection::open(db_path)?,
+        })
+    }
+
+    /// Applies the pending schema migrations, returns the ones applied
+    pub fn migrate(&mut self) -> Result<Vec<&'static Migration>> {
+        migrations::run(&mut self.conn)
+    }
+
+    /// Version of the database schema, 0 before the first migration
+    pub fn schema_version(&self) -> Result<i64> {
+        migrations::current_version(&self.conn)
+    }
+
+    /// Migrations the database is still missing
+    pub fn pending_migrations(&self) -> Result<Vec<&'static Migration>> {
+        migrations::pending(&self.conn)
+    }
+
+    /// Moves the jsonl lines of `(id, metadata)` into typed columns, rowids are
+    /// kept so the embeddings, manifest and FTS5 index still match
+    pub(crate) fn metadata_v2(conn: &Connection, collection: &CollectionName) -> Result<()> {
+        let old_table = collection.table_for("metadata_v1");
+        conn.execute(
+            &format!(
+                "ALTER TABLE {} RENAME TO {}",
+                collection.table_for("metadata"),
+                old_table
+            ),
+            [],
+        )?;
+        Self::create_metadata(conn, collection)?;
+        let mut select = conn.prepare(&format!("SELECT id, metadata FROM {}", old_table))?;
+        let mut insert = conn.prepare(&Self::insert_metadata_sql(collection))?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            // 旧表的 metadata 是 BLOB 列, 可能存成了 TEXT 或 BLOB
+            let metadata = match row.get_ref(1)? {
+                rusqlite::types::ValueRef::Text(b) | rusqlite::types::ValueRef::Blob(b) => {
+                    String::from_utf8_lossy(b).to_string()
+                }
+                _ => String::new(),
+            };
+            let mut values = vec![rusqlite::types::Value::from(id)];
+            values.extend(StoredChunk::parse(&metadata).values());
+            insert.execute(rusqlite::params_from_iter(values))?;
+        }
+        conn.execute(&format!("DROP TABLE {}", old_table), [])?;
+        Ok(())
+    }
+
+    /// Creates the metadata table of a collection with its indexes
+    fn create_metadata(conn: &Connection, collection: &CollectionName) -> Result<()> {
+        let table = collection.table_for("metadata");
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id INTEGER PRIMARY KEY,
+                    chunk_id TEXT,
+                    source TEXT,
+                    section TEXT,
+                    anchor TEXT,
+                    position INTEGER,
+                    text TEXT NOT NULL,
+                    token_count INTEGER NOT NULL,
+                    version TEXT,
+                    content_hash TEXT NOT NULL
+                )",
+                table
+            ),
+            [],
+        )?;
+        conn.execute(
+            &format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} (chunk_id)",
+                collection.table_for("metadata_chunk_id"),
+                table
+            ),
+            [],
+        )?;
+        conn.execute(
+            &format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} (version, source)",
+                collection.table_for("metadata_source"),
+                table
+            ),
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// `INSERT` of one metadata row, `?1` is the id followed by [`StoredChunk::values`]
+    fn insert_metadata_sql(collection: &CollectionName) -> String {
+        format!(
+            "INSERT INTO {} (id, {}) VALUES ({})",
+            collection.table_for("metadata"),
+            METADATA_COLUMNS.join(", "),
+            vec!["?"; METADATA_COLUMNS.len() + 1].join(", ")
+        )
+    }
+
+    /// Adds the vec0 tables created before the catalog existed to it, tables
+    /// whose name is not a valid collection name stay out of reach
+    pub(crate) fn register_existing(conn: &Connection) -> Result<()> {
+        let mut stmt = conn.prepare(
+            "SELECT name FROM sqlite_master
+             WHERE type = 'table' AND sql LIKE 'CREATE VIRTUAL TABLE%USING vec0%'
+               AND name NOT IN (SELECT name FROM collections)",
+        )?;
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for name in names {
+            match CollectionName::new(&name) {
+                Ok(name) => Self::register(conn, &name, 0)?,
+                Err(e) => log::warn!("Skipping table `{}`: {}", name, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a collection to the catalog, its model is recorded later by `record_model`
+    fn register(conn: &Connection, collection: &CollectionName, dimension: u32) -> Result<()> {
+        conn.execute(
+            "INSERT INTO collections (name, model, dimension, created_at)
+             VALUES (?1, '', ?2, ?3)
+             ON CONFLICT(name) DO NOTHING",
+            params![
+                collection.as_str(),
+                dimension,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Fails unless the collection is in the catalog, the only tables queries may touch
+    fn ensure_registered(&self, collection: &CollectionName) -> Result<()> {
+        let registered: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM collections WHERE name = ?1)",
+            params![collection.as_str()],
+            |row| row.get(0),
+        )?;
+        if !registered {
+            bail!("collection `{}` does not exist", collection);
+        }
+        Ok(())
+    }
+
+    /// Commit a collection (or one docs version of it) was last synced from
+    pub fn indexed_commit(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+    ) -> Result<Option<String>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT commit_sha FROM sync_state WHERE collection = ?1 AND version = ?2",
+                params![collection.as_str(), version.unwrap_or_default()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Records the commit a collection was synced from
+    pub fn set_indexed_commit(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+        url: &str,
+        commit_sha: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO sync_state (collection, version, url, commit_sha, synced_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(collection, version) DO UPDATE SET
+                url = excluded.url,
+                commit_sha = excluded.commit_sha,
+                synced_at = excluded.synced_at",
+            params![
+                collection.as_str(),
+                version.unwrap_or_default(),
+                url,
+                commit_sha,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Splitter settings a collection (or one docs version of it) was last
+    /// chunked with, `None` for versions ingested before they were recorded
+    pub fn chunk_settings(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+    ) -> Result<Option<ChunkSettings>> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT splitter, chunk_size, chunk_overlap FROM chunk_settings
+                 WHERE collection = ?1 AND version = ?2",
+                params![collection.as_str(), version.unwrap_or_default()],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        row.map(|(mode, chunk_size, chunk_overlap)| {
+            Ok(ChunkSettings {
+                mode: mode.parse()?,
+                chunk_size: chunk_size as usize,
+                chunk_overlap: chunk_overlap as usize,
+            })
+        })
+        .transpose()
+    }
+
+    /// Overlap the chunks of a docs version repeat from the chunk before them
+    pub fn chunk_overlap(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+    ) -> Result<usize> {
+        Ok(self
+            .chunk_settings(collection, version)?
+            .map_or(DEFAULT_CHUNK_OVERLAP, |s| s.chunk_overlap))
+    }
+
+    /// Records the splitter settings a docs version was chunked with
+    pub fn set_chunk_settings(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+        settings: &ChunkSettings,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO chunk_settings (collection, version, splitter, chunk_size, chunk_overlap, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(collection, version) DO UPDATE SET
+                splitter = excluded.splitter,
+                chunk_size = excluded.chunk_size,
+                chunk_overlap = excluded.chunk_overlap,
+                updated_at = excluded.updated_at",
+            params![
+                collection.as_str(),
+                version.unwrap_or_default(),
+                settings.mode.to_string(),
+                settings.chunk_size as i64,
+                settings.chunk_overlap as i64,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores a search in `query_log` and returns its id
+    pub fn log_query(&self, entry: &QueryLogEntry) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO query_log (collection, query, mode, version, client, hits, latency_ms, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                entry.collection,
+                entry.query,
+                entry.mode,
+                entry.version,
+                entry.client,
+                serde_json::to_string(&entry.hits)?,
+                entry.latency_ms,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Search logged under `id`
+    pub fn logged_query(&self, id: i64) -> Result<Option<QueryLogEntry>> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT collection, query, mode, version, client, hits, latency_ms
+                 FROM query_log WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok((
+                        QueryLogEntry {
+                            collection: row.get(0)?,
+                            query: row.get(1)?,
+                            mode: row.get(2)?,
+                            version: row.get(3)?,
+                            client: row.get(4)?,
+                            hits: Vec::new(),
+                            latency_ms: row.get(6)?,
+                        },
+                        row.get::<_, String>(5)?,
+                    ))
+                },
+            )
+            .optional()?;
+        row.map(|(entry, hits)| {
+            Ok(QueryLogEntry {
+                hits: serde_json::from_str(&hits)?,
+                ..entry
+            })
+        })
+        .transpose()
+    }
+
+    /// Marks chunks of a logged search as helpful, returns how many were not marked yet
+    pub fn record_feedback(&self, query_id: i64, chunk_ids: &[String]) -> Result<usize> {
+        let mut stmt = self.conn.prepare(
+            "INSERT OR IGNORE INTO query_feedback (query_id, chunk_id, created_at)
+             VALUES (?1, ?2, ?3)",
+        )?;
+        let now = chrono::Local::now().to_rfc3339();
+        let mut added = 0;
+        for id in chunk_ids {
+            added += stmt.execute(params![query_id, id, now])?;
+        }
+        Ok(added)
+    }
+
+    /// Model and dimension a collection was built with, `None` for collections
+    /// ingested before they were recorded
+    pub fn collection_model(&self, collection: &CollectionName) -> Result<Option<(String, usize)>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT model, dimension FROM collections WHERE name = ?1 AND model != ''",
+                params![collection.as_str()],
+                |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)),
+            )
+            .optional()?)
+    }
 
-        Ok(Self { conn })
+    /// Records the model and dimension of a collection
+    pub fn record_model(
+        &self,
+        collection: &CollectionName,
+        model: &str,
+        dimension: usize,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO collections (name, model, dimension, created_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(name) DO UPDATE SET model = excluded.model, dimension = excluded.dimension",
+            params![
+                collection.as_str(),
+                model,
+                dimension as i64,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
     }
 
-    /// Creates a vector collection with the specified name and parameters
-    pub fn create_vector_collection(&self, name: &str, params: VectorParams) -> Result<()> {
+    /// Creates a vector collection with the specified name and parameters and
+    /// adds it to the catalog
+    pub fn create_vector_collection(
+        &self,
+        name: &CollectionName,
+        params: VectorParams,
+    ) -> Result<()> {
         let sql = format!(
             "CREATE VIRTUAL TABLE IF NOT EXISTS {} USING vec0(embedding FLOAT[{}])",
-            name, params.dimension
+            name.table(),
+            params.dimension
         );
 
         println!("Executing SQL: {}", sql);
         self.conn.execute(&sql, [])?;
+        // IF NOT EXISTS 遇到同名的普通表时什么也不做, 不能把它登记成集合
+        let is_vec0: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master
+             WHERE type = 'table' AND name = ?1 AND sql LIKE 'CREATE VIRTUAL TABLE%USING vec0%')",
+            params![name.as_str()],
+            |row| row.get(0),
+        )?;
+        if !is_vec0 {
+            bail!(
+                "table `{}` already exists and is not a vector collection",
+                name
+            );
+        }
+        Self::register(&self.conn, name, params.dimension)?;
         self.set_metadata(name)?;
+        self.create_manifest(name)?;
+        self.create_fts(name)?;
+        self.create_pages(name)?;
+        Ok(())
+    }
+
+    /// Creates the table holding the original markdown of every source page
+    pub fn create_pages(&self, collection: &CollectionName) -> Result<()> {
+        self.ensure_registered(collection)?;
+        self.conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    version TEXT NOT NULL DEFAULT '',
+                    source TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    updated_at TEXT NOT NULL,
+                    PRIMARY KEY (version, source)
+                )",
+                collection.table_for("pages")
+            ),
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Stores the original markdown of a source page
+    pub fn store_page(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+        source: &str,
+        path: &str,
+        content: &str,
+    ) -> Result<()> {
+        self.ensure_registered(collection)?;
+        self.conn.execute(
+            &format!(
+                "INSERT INTO {} (version, source, path, content, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(version, source) DO UPDATE SET
+                    path = excluded.path,
+                    content = excluded.content,
+                    updated_at = excluded.updated_at",
+                collection.table_for("pages")
+            ),
+            params![
+                version.unwrap_or_default(),
+                source,
+                path,
+                content,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
         Ok(())
     }
 
+    /// Source pages of a collection, ordered by version and path
+    pub fn pages(&self, collection: &CollectionName) -> Result<Vec<PageEntry>> {
+        self.create_manifest(collection)?;
+        self.create_pages(collection)?;
+        let sql = format!(
+            "SELECT m.version, m.source, p.path, LENGTH(CAST(p.content AS BLOB))
+             FROM {} m
+             LEFT JOIN {} p ON p.version = m.version AND p.source = m.source",
+            collection.table_for("manifest"),
+            collection.table_for("pages")
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+            ))
+        })?;
+
+        let mut pages = Vec::new();
+        for row in rows {
+            let (version, source, path, size) = row?;
+            let path = path.unwrap_or_else(|| {
+                Path::new(&source)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| source.clone())
+            });
+            pages.push(PageEntry {
+                version: Some(version).filter(|v| !v.is_empty()),
+                source,
+                path,
+                size: size.map(|s| s as usize),
+            });
+        }
+        pages.sort_by(|a, b| (&a.version, &a.path).cmp(&(&b.version, &b.path)));
+        Ok(pages)
+    }
+
+    /// Chunks with the given ids, in the order of the ids, ids that do not exist are skipped
+    pub fn chunks_by_ids(
+        &self,
+        collection: &CollectionName,
+        ids: &[String],
+    ) -> Result<Vec<StoredChunk>> {
+        self.ensure_registered(collection)?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT {} FROM {} m WHERE chunk_id IN ({})",
+            metadata_columns("m"),
+            collection.table_for("metadata"),
+            vec!["?"; ids.len()].join(", ")
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut chunks = stmt
+            .query_map(rusqlite::params_from_iter(ids), |row| {
+                StoredChunk::from_row(row, 0)
+            })?
+            .filter_map(|chunk| chunk.transpose())
+            .map(|chunk| chunk.map(|c| (c.chunk_id.clone().unwrap_or_default(), c)))
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(ids.iter().filter_map(|id| chunks.remove(id)).collect())
+    }
+
+    /// Full markdown of a source page, the stored original or, for pages
+    /// ingested before it was stored, its chunks stitched back together
+    pub fn page_content(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+        source: &str,
+    ) -> Result<Option<String>> {
+        self.create_pages(collection)?;
+        let content: Option<String> = self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT content FROM {} WHERE version = ?1 AND source = ?2",
+                    collection.table_for("pages")
+                ),
+                params![version.unwrap_or_default(), source],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if content.is_some() {
+            return Ok(content);
+        }
+
+        let Some(entry) = self.load_manifest(collection, version)?.remove(source) else {
+            return Ok(None);
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT text FROM {} WHERE id = ?1",
+            collection.table_for("metadata")
+        ))?;
+        let mut texts = Vec::with_capacity(entry.rowids.len());
+        for id in &entry.rowids {
+            if let Some(text) = stmt
+                .query_row(params![id], |row| row.get::<_, String>(0))
+                .optional()?
+            {
+                texts.push(text);
+            }
+        }
+        Ok(Some(stitch_chunks(
+            &texts.iter().map(|t| t.as_str()).collect::<Vec<_>>(),
+            self.chunk_overlap(collection, version)?,
+        )))
+    }
+
+    /// Creates the FTS5 keyword index of a collection, filling it from the
+    /// metadata table when the collection was ingested before the index existed
+    pub fn create_fts(&self, collection: &CollectionName) -> Result<()> {
+        self.ensure_registered(collection)?;
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+            params![collection.table_name("fts")],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Ok(());
+        }
+
+        // `_` 算作词内字符, 这样 has_many / snake_case 标识符不会被拆开
+        self.conn.execute(
+            &format!(
+                "CREATE VIRTUAL TABLE {} USING fts5(text, section, tokenize = \"unicode61 tokenchars '_'\")",
+                collection.table_for("fts")
+            ),
+            [],
+        )?;
+        self.conn.execute(
+            &format!(
+                "INSERT INTO {} (rowid, text, section) {} ",
+                collection.table_for("fts"),
+                Self::fts_select(collection)
+            ),
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Selects the FTS5 columns from metadata rows
+    fn fts_select(collection: &CollectionName) -> String {
+        format!(
+            "SELECT id, text, section FROM {}",
+            collection.table_for("metadata")
+        )
+    }
+
+    /// Copies metadata rows into the FTS5 index
+    fn index_fts(conn: &Connection, collection: &CollectionName, ids: &[i64]) -> Result<()> {
+        let mut stmt = conn.prepare(&format!(
+            "INSERT INTO {} (rowid, text, section) {} WHERE id = ?",
+            collection.table_for("fts"),
+            Self::fts_select(collection)
+        ))?;
+        for id in ids {
+            stmt.execute(params![id])?;
+        }
+        Ok(())
+    }
+
+    /// Creates the manifest table that tracks which rowids each source file
+    /// produced, one entry per docs version and source
+    pub fn create_manifest(&self, collection: &CollectionName) -> Result<()> {
+        self.ensure_registered(collection)?;
+        let manifest_name = collection.table_name("manifest");
+        let manifest_table = collection.table_for("manifest");
+        let old_table = collection.table_for("manifest_old");
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = ?1)",
+            params![manifest_name],
+            |row| row.get(0),
+        )?;
+        let has_version: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = 'version')",
+            params![manifest_name],
+            |row| row.get(0),
+        )?;
+
+        let create_sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                version TEXT NOT NULL DEFAULT '',
+                source TEXT NOT NULL,
+                content_hash TEXT NOT NULL,
+                rowids TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (version, source)
+            )",
+            manifest_table
+        );
+
+        if exists && !has_version {
+            // 旧的 manifest 以 source 为主键, 重建表后旧数据归入未标记版本
+            let tx = self.conn.unchecked_transaction()?;
+            tx.execute(
+                &format!("ALTER TABLE {} RENAME TO {}", manifest_table, old_table),
+                [],
+            )?;
+            tx.execute(&create_sql, [])?;
+            tx.execute(
+                &format!(
+                    "INSERT INTO {} (source, content_hash, rowids, updated_at)
+                     SELECT source, content_hash, rowids, updated_at FROM {}",
+                    manifest_table, old_table
+                ),
+                [],
+            )?;
+            tx.execute(&format!("DROP TABLE {}", old_table), [])?;
+            tx.commit()?;
+            return Ok(());
+        }
+
+        self.conn.execute(&create_sql, [])?;
+        Ok(())
+    }
+
+    /// Whether any file of any version has been recorded in the manifest
+    pub fn has_manifest(&self, collection: &CollectionName) -> Result<bool> {
+        self.ensure_registered(collection)?;
+        Ok(self.conn.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM {})",
+                collection.table_for("manifest")
+            ),
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Loads the manifest of one docs version of a collection keyed by source path
+    pub fn load_manifest(
+        &self,
+        collection: &CollectionName,
+        version: Option<&str>,
+    ) -> Result<HashMap<String, ManifestEntry>> {
+        self.ensure_registered(collection)?;
+        let sql = format!(
+            "SELECT source, content_hash, rowids FROM {} WHERE version = ?1",
+            collection.table_for("manifest")
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params![version.unwrap_or_default()], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut manifest = HashMap::new();
+        for row in rows {
+            let (source, content_hash, rowids) = row?;
+            let rowids: Vec<i64> = serde_json::from_str(&rowids)?;
+            manifest.insert(
+                source,
+                ManifestEntry {
+                    content_hash,
+                    rowids,
+                },
+            );
+        }
+        Ok(manifest)
+    }
+
+    /// Replaces every row of one source file in a single transaction and
+    /// returns the rowids assigned to the new rows
+    pub fn replace_source(
+        &mut self,
+        collection: &CollectionName,
+        version: Option<&str>,
+        source: &str,
+        content_hash: &str,
+        items: Vec<(&[f32], &str)>,
+    ) -> Result<Vec<i64>> {
+        self.ensure_registered(collection)?;
+        let meta_table = collection.table_for("metadata");
+        let manifest_table = collection.table_for("manifest");
+
+        let tx = self.conn.transaction()?;
+        Self::delete_source_rows(&tx, collection, version, source)?;
+
+        let next_id: i64 = tx.query_row(
+            &format!("SELECT COALESCE(MAX(id), 0) + 1 FROM {}", meta_table),
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut rowids = Vec::with_capacity(items.len());
+        {
+            let mut vec_stmt = tx.prepare(&format!(
+                "insert into {} (rowid, embedding) values (?, ?)",
+                collection.table()
+            ))?;
+            let mut meta_stmt = tx.prepare(&Self::insert_metadata_sql(collection))?;
+            for (offset, (embedding, metadata)) in items.into_iter().enumerate() {
+                let id = next_id + offset as i64;
+                vec_stmt.execute(params![id, embedding_bytes(embedding)])?;
+                let mut values = vec![rusqlite::types::Value::from(id)];
+                values.extend(StoredChunk::parse(metadata).values());
+                meta_stmt.execute(rusqlite::params_from_iter(values))?;
+                rowids.push(id);
+            }
+        }
+        Self::index_fts(&tx, collection, &rowids)?;
+
+        tx.execute(
+            &format!(
+                "INSERT INTO {} (version, source, content_hash, rowids, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(version, source) DO UPDATE SET
+                    content_hash = excluded.content_hash,
+                    rowids = excluded.rowids,
+                    updated_at = excluded.updated_at",
+                manifest_table
+            ),
+            params![
+                version.unwrap_or_default(),
+                source,
+                content_hash,
+                serde_json::to_string(&rowids)?,
+                chrono::Local::now().to_rfc3339()
+            ],
+        )?;
+        tx.commit()?;
+        Ok(rowids)
+    }
+
+    /// Removes every row of one source file together with its manifest entry
+    /// and page, returns how many rows were removed
+    pub fn delete_by_source(
+        &mut self,
+        collection: &CollectionName,
+        version: Option<&str>,
+        source: &str,
+    ) -> Result<usize> {
+        self.ensure_registered(collection)?;
+        self.create_manifest(collection)?;
+        self.create_fts(collection)?;
+        self.create_pages(collection)?;
+        let tx = self.conn.transaction()?;
+        let removed = Self::delete_source_rows(&tx, collection, version, source)?;
+        tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE version = ?1 AND source = ?2",
+                collection.table_for("manifest")
+            ),
+            params![version.unwrap_or_default(), source],
+        )?;
+        tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE version = ?1 AND source = ?2",
+                collection.table_for("pages")
+            ),
+            params![version.unwrap_or_default(), source],
+        )?;
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Removes rows by rowid and drops them from the manifest entries that
+    /// listed them, returns how many existed
+    pub fn delete_by_ids(&mut self, collection: &CollectionName, ids: &[i64]) -> Result<usize> {
+        self.ensure_registered(collection)?;
+        self.create_manifest(collection)?;
+        self.create_fts(collection)?;
+        let tx = self.conn.transaction()?;
+        let removed = Self::delete_rows(&tx, collection, ids)?;
+
+        let manifest_table = collection.table_for("manifest");
+        let entries = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT version, source, rowids FROM {}",
+                manifest_table
+            ))?;
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let ids: HashSet<i64> = ids.iter().copied().collect();
+        for (version, source, rowids) in entries {
+            let rowids: Vec<i64> = serde_json::from_str(&rowids)?;
+            let kept = rowids
+                .iter()
+                .copied()
+                .filter(|id| !ids.contains(id))
+                .collect::<Vec<_>>();
+            // content_hash 保持不变, 下次同步不会把删掉的行再写回来
+            if kept.len() != rowids.len() {
+                tx.execute(
+                    &format!(
+                        "UPDATE {} SET rowids = ?3 WHERE version = ?1 AND source = ?2",
+                        manifest_table
+                    ),
+                    params![version, source, serde_json::to_string(&kept)?],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Writes the embedding and metadata of one row in a single transaction,
+    /// replacing both when the rowid is taken
+    pub fn upsert_chunk(
+        &mut self,
+        collection: &CollectionName,
+        id: i64,
+        embedding: &[f32],
+        metadata: &str,
+    ) -> Result<()> {
+        self.ensure_registered(collection)?;
+        self.create_fts(collection)?;
+        let tx = self.conn.transaction()?;
+        // vec0 不支持 INSERT OR REPLACE, 先删掉旧行再插入
+        Self::delete_rows(&tx, collection, &[id])?;
+        tx.execute(
+            &format!(
+                "insert into {} (rowid, embedding) values (?, ?)",
+                collection.table()
+            ),
+            params![id, embedding_bytes(embedding)],
+        )?;
+        let mut values = vec![rusqlite::types::Value::from(id)];
+        values.extend(StoredChunk::parse(metadata).values());
+        tx.execute(
+            &Self::insert_metadata_sql(collection),
+            rusqlite::params_from_iter(values),
+        )?;
+        Self::index_fts(&tx, collection, &[id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Rowids of one source file: those of its manifest entry, and those whose
+    /// metadata names it for rows written without a manifest by `store_docs`
+    fn source_rowids(
+        conn: &Connection,
+        collection: &CollectionName,
+        version: Option<&str>,
+        source: &str,
+    ) -> Result<Vec<i64>> {
+        let rowids: Option<String> = conn
+            .query_row(
+                &format!(
+                    "SELECT rowids FROM {} WHERE version = ?1 AND source = ?2",
+                    collection.table_for("manifest")
+                ),
+                params![version.unwrap_or_default(), source],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut ids: Vec<i64> = match rowids {
+            Some(r) => serde_json::from_str(&r)?,
+            None => Vec::new(),
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id FROM {} WHERE source = ?1 AND version IS ?2",
+            collection.table_for("metadata")
+        ))?;
+        for id in stmt.query_map(params![source, version], |row| row.get(0))? {
+            ids.push(id?);
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    fn delete_source_rows(
+        conn: &Connection,
+        collection: &CollectionName,
+        version: Option<&str>,
+        source: &str,
+    ) -> Result<usize> {
+        let rowids = Self::source_rowids(conn, collection, version, source)?;
+        Self::delete_rows(conn, collection, &rowids)
+    }
+
+    /// Deletes rows from the vec0, metadata and FTS5 tables, returns how many
+    /// metadata rows existed
+    fn delete_rows(conn: &Connection, collection: &CollectionName, ids: &[i64]) -> Result<usize> {
+        let mut vec_stmt = conn.prepare(&format!(
+            "DELETE FROM {} WHERE rowid = ?",
+            collection.table()
+        ))?;
+        let mut meta_stmt = conn.prepare(&format!(
+            "DELETE FROM {} WHERE id = ?",
+            collection.table_for("metadata")
+        ))?;
+        let mut fts_stmt = conn.prepare(&format!(
+            "DELETE FROM {} WHERE rowid = ?",
+            collection.table_for("fts")
+        ))?;
+        let mut removed = 0;
+        for id in ids {
+            vec_stmt.execute(params![id])?;
+            removed += meta_stmt.execute(params![id])?;
+            fts_stmt.execute(params![id])?;
+        }
+        Ok(removed)
+    }
+
+    /// Collections of the catalog whose vec0 table exists
+    pub fn collections(&self) -> Result<Vec<CollectionName>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT c.name FROM collections c
+             JOIN sqlite_master m ON m.name = c.name
+             WHERE m.type = 'table' AND m.sql LIKE 'CREATE VIRTUAL TABLE%USING vec0%'
+             ORDER BY c.name",
+        )?;
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        names.iter().map(|name| CollectionName::new(name)).collect()
+    }
+
+    /// Whether a vec0 collection of this name is in the catalog, without counting its rows
+    pub fn has_collection(&self, collection: &CollectionName) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM collections c
+             JOIN sqlite_master m ON m.name = c.name
+             WHERE c.name = ?1 AND m.type = 'table'
+               AND m.sql LIKE 'CREATE VIRTUAL TABLE%USING vec0%')",
+            params![collection.as_str()],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Number of rows stored in a collection
+    pub fn count(&self, collection: &CollectionName) -> Result<usize> {
+        self.ensure_registered(collection)?;
+        let count: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM {}", collection.table_for("metadata")),
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
     /// Adds an item to the vector collection
-    pub fn add_item(&self, collection: &str, embedding: &[f32]) -> Result<()> {
-        let sql = format!("insert into {} (embedding) values (?)", collection);
+    pub fn add_item(&self, collection: &CollectionName, embedding: &[f32]) -> Result<()> {
+        self.ensure_registered(collection)?;
+        let sql = format!("insert into {} (embedding) values (?)", collection.table());
         let mut stmt = self.conn.prepare(sql.as_str())?;
 
         let byte_slice = unsafe {
@@ -67,56 +1476,67 @@ impl SqliteVector {
     }
 
     /// Adds an item to the vector collection
-    pub fn add_mate(&self, collection: &str, id: usize, mate_data: &str) -> Result<()> {
-        let meta_table = format!("{}_metadata", collection);
-        let sql = format!("insert into {} (id, metadata) values (?, ?)", meta_table);
-        let mut stmt = self.conn.prepare(sql.as_str())?;
+    pub fn add_mate(&self, collection: &CollectionName, id: usize, mate_data: &str) -> Result<()> {
+        self.ensure_registered(collection)?;
+        let mut stmt = self.conn.prepare(&Self::insert_metadata_sql(collection))?;
 
-        stmt.execute(rusqlite::params![id, mate_data])?;
+        let mut values = vec![rusqlite::types::Value::from(id as i64)];
+        values.extend(StoredChunk::parse(mate_data).values());
+        stmt.execute(rusqlite::params_from_iter(values))?;
+        Self::index_fts(&self.conn, collection, &[id as i64])?;
         Ok(())
     }
 
-    /// Sets metadata for an item
-    pub fn set_metadata(&self, collection: &str) -> Result<()> {
-        // Create metadata table if it doesn't exist
-        let meta_table = format!("{}_metadata", collection);
-        let create_sql = format!(
-            "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, metadata BLOB)",
-            meta_table
-        );
-
-        println!("Executing metadata SQL: {}", create_sql);
-        self.conn.execute(&create_sql, [])?;
-
-        Ok(())
+    /// Creates the metadata table of a collection if it doesn't exist
+    pub fn set_metadata(&self, collection: &CollectionName) -> Result<()> {
+        self.ensure_registered(collection)?;
+        Self::create_metadata(&self.conn, collection)
     }
 
     /// Performs a similarity search
     pub fn search(
         &self,
-        collection: &str,
+        collection: &CollectionName,
         embedding: &[f32],
         limit: u32,
-    ) -> Result<Vec<(i64, Option<String>)>> {
-        let meta_table = format!("{}_metadata", collection);
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchHit>> {
+        self.ensure_registered(collection)?;
+        if !filter.source_prefixes.is_empty() {
+            self.create_pages(collection)?;
+        }
+        // rowid IN 条件由 sqlite-vec 在 KNN 之前应用, k 个结果都满足过滤条件
+        let (filter_sql, filter_params) = filter.rowid_clause(collection, "v.rowid", 3);
 
         let sql = format!(
-            "SELECT v.rowid, m.metadata
+            "SELECT v.rowid, v.distance, {}
              FROM {} v
              LEFT JOIN {} m ON v.rowid = m.id
-             WHERE v.embedding MATCH ?1 AND k=?2
+             WHERE v.embedding MATCH ?1 AND k=?2{}
              ORDER BY distance
              LIMIT ?2",
-            collection, meta_table,
+            metadata_columns("m"),
+            collection.table(),
+            collection.table_for("metadata"),
+            filter_sql,
         );
 
         println!("Executing search SQL: {}", sql);
         let mut stmt = self.conn.prepare(&sql)?;
         // let e= embedding;
-        let rows = stmt.query_map(params![cast_slice(embedding), limit as i64], |row| {
-            let id: i64 = row.get(0)?;
-            let metadata: Option<String> = row.get(1)?;
-            Ok((id, metadata))
+        let mut values: Vec<rusqlite::types::Value> = vec![
+            embedding_bytes(embedding).to_vec().into(),
+            (limit as i64).into(),
+        ];
+        values.extend(filter_params);
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            let distance: f64 = row.get(1)?;
+            Ok(SearchHit {
+                rowid: row.get(0)?,
+                chunk: StoredChunk::from_row(row, 2)?,
+                distance: Some(distance),
+                score: similarity(distance),
+            })
         })?;
 
         let mut results = Vec::new();
@@ -127,6 +1547,62 @@ impl SqliteVector {
         Ok(results)
     }
 
+    /// Performs a BM25 keyword search over the FTS5 index
+    pub fn keyword_search(
+        &self,
+        collection: &CollectionName,
+        text: &str,
+        limit: u32,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchHit>> {
+        self.ensure_registered(collection)?;
+        let Some(query) = fts_query(text) else {
+            return Ok(Vec::new());
+        };
+        if !filter.source_prefixes.is_empty() {
+            self.create_pages(collection)?;
+        }
+        let (filter_sql, filter_params) = filter.rowid_clause(collection, "f.rowid", 3);
+        let sql = format!(
+            "SELECT f.rowid, bm25({0}) AS rank, {1}
+             FROM {0} f
+             LEFT JOIN {2} m ON f.rowid = m.id
+             WHERE {0} MATCH ?1{3}
+             ORDER BY rank
+             LIMIT ?2",
+            collection.table_for("fts"),
+            metadata_columns("m"),
+            collection.table_for("metadata"),
+            filter_sql
+        );
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut values: Vec<rusqlite::types::Value> = vec![query.into(), (limit as i64).into()];
+        values.extend(filter_params);
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            let rank: f64 = row.get(1)?;
+            Ok(SearchHit {
+                rowid: row.get(0)?,
+                chunk: StoredChunk::from_row(row, 2)?,
+                distance: None,
+                score: -rank,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Docs versions stored in a collection with their chunk counts, `None` for untagged chunks
+    pub fn versions(&self, collection: &CollectionName) -> Result<Vec<(Option<String>, usize)>> {
+        self.ensure_registered(collection)?;
+        let sql = format!(
+            "SELECT version, COUNT(*) FROM {} GROUP BY version ORDER BY version",
+            collection.table_for("metadata")
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
     pub fn generate_batch_sql(base_sql: &str, items_len: usize, value_format: &str) -> String {
         // 提前设置字符串长度 items_len - 1 这部分是 , 的长度
         let total_length = base_sql.len() + value_format.len() * items_len + items_len - 1;
@@ -139,44 +1615,55 @@ impl SqliteVector {
         result
     }
 
-    pub fn add_items(&mut self, collection: &str, items: Vec<(usize, &[f32])>) -> Result<()> {
+    pub fn add_items(
+        &mut self,
+        collection: &CollectionName,
+        items: Vec<(usize, &[f32])>,
+    ) -> Result<()> {
+        self.ensure_registered(collection)?;
+        // rowid 显式写入, 保证与 metadata 的 id 一致
         self.batch_insert(
-            collection,
-            "(embedding)",
-            "(?)",
-            items.into_iter().map(|(_, embedding)| {
-                let byte_slice = unsafe {
-                    std::slice::from_raw_parts(
-                        embedding.as_ptr() as *const u8,
-                        std::mem::size_of_val(embedding),
-                    )
-                };
+            collection.table(),
+            "(rowid, embedding)",
+            "(?, ?)",
+            items.into_iter().map(|(id, embedding)| {
                 vec![
-                    // rusqlite::types::Value::from(id as i64),
-                    rusqlite::types::Value::from(byte_slice.to_vec()),
+                    rusqlite::types::Value::from(id as i64),
+                    rusqlite::types::Value::from(embedding_bytes(embedding).to_vec()),
                 ]
             }),
         )
     }
 
     /// 批量插入 metadata
-    pub fn add_mates(&mut self, collection: &str, mates: Vec<(usize, &str)>) -> Result<()> {
+    pub fn add_mates(
+        &mut self,
+        collection: &CollectionName,
+        mates: Vec<(usize, &str)>,
+    ) -> Result<()> {
+        self.ensure_registered(collection)?;
+        let ids = mates.iter().map(|(id, _)| *id as i64).collect::<Vec<_>>();
         self.batch_insert(
-            &format!("{}_metadata", collection),
-            "(id, metadata)",
-            "(?, ?)",
+            collection.table_for("metadata"),
+            &format!("(id, {})", METADATA_COLUMNS.join(", ")),
+            &format!("({})", vec!["?"; METADATA_COLUMNS.len() + 1].join(", ")),
             mates.into_iter().map(|(id, text)| {
-                vec![
-                    rusqlite::types::Value::from(id as i64),
-                    rusqlite::types::Value::from(text.to_string()),
-                ]
+                let mut values = vec![rusqlite::types::Value::from(id as i64)];
+                values.extend(StoredChunk::parse(text).values());
+                values
             }),
-        )
+        )?;
+
+        let tx = self.conn.transaction()?;
+        Self::index_fts(&tx, collection, &ids)?;
+        tx.commit()?;
+        Ok(())
     }
 
+    /// Inserts rows into `table`, an identifier already quoted by [`CollectionName`]
     fn batch_insert<I>(
         &mut self,
-        collection: &str,
+        table: String,
         columns: &str,
         value_format: &str,
         rows: I,
@@ -190,7 +1677,7 @@ impl SqliteVector {
         }
 
         let batch_sql = Self::generate_batch_sql(
-            &format!("insert into {} {} values ", collection, columns),
+            &format!("insert into {} {} values ", table, columns),
             rows.len(),
             value_format,
         );
@@ -203,94 +1690,417 @@ impl SqliteVector {
         tx.commit()?;
         Ok(())
     }
+
+    /// Removes every row, manifest entry and page of one docs version. The
+    /// whole collection is dropped when no other version is stored in it, so
+    /// a rebuild of its only version can still switch models
+    pub fn drop_version(
+        &mut self,
+        collection: &CollectionName,
+        version: Option<&str>,
+    ) -> Result<()> {
+        if self.ensure_registered(collection).is_err() {
+            return Ok(());
+        }
+        self.set_metadata(collection)?;
+        self.create_manifest(collection)?;
+        self.create_fts(collection)?;
+        self.create_pages(collection)?;
+        let others: bool = self.conn.query_row(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM {} WHERE version IS NOT ?1)
+                     OR EXISTS (SELECT 1 FROM {} WHERE version != ?2)",
+                collection.table_for("metadata"),
+                collection.table_for("manifest")
+            ),
+            params![version, version.unwrap_or_default()],
+            |row| row.get(0),
+        )?;
+        if !others {
+            return self.drop_collection(collection);
+        }
+
+        let tx = self.conn.transaction()?;
+        // manifest 记录的行和 metadata 标记为该版本的行都要删掉, 后者包括没有 manifest 的旧数据
+        let mut ids = Vec::new();
+        {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT rowids FROM {} WHERE version = ?1",
+                collection.table_for("manifest")
+            ))?;
+            for rowids in stmt.query_map(params![version.unwrap_or_default()], |row| {
+                row.get::<_, String>(0)
+            })? {
+                ids.extend(serde_json::from_str::<Vec<i64>>(&rowids?)?);
+            }
+            let mut stmt = tx.prepare(&format!(
+                "SELECT id FROM {} WHERE version IS ?1",
+                collection.table_for("metadata")
+            ))?;
+            for id in stmt.query_map(params![version], |row| row.get(0))? {
+                ids.push(id?);
+            }
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        Self::delete_rows(&tx, collection, &ids)?;
+        for suffix in ["manifest", "pages"] {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE version = ?1",
+                    collection.table_for(suffix)
+                ),
+                params![version.unwrap_or_default()],
+            )?;
+        }
+        // 其它版本的同步提交保持不变, 下次 sync 只会全量比对这个版本
+        for table in ["sync_state", "chunk_settings"] {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE collection = ?1 AND version = ?2",
+                    table
+                ),
+                params![collection.as_str(), version.unwrap_or_default()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Drops every table of a collection and forgets it together with the
+    /// synced commits of all its versions, collections missing from the
+    /// catalog are left alone. Use `drop_version` to remove a single version
+    pub fn drop_collection(&self, collection: &CollectionName) -> Result<()> {
+        if self.ensure_registered(collection).is_err() {
+            return Ok(());
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(&format!("DROP TABLE IF EXISTS {}", collection.table()), [])?;
+        for suffix in ["metadata", "manifest", "fts", "pages"] {
+            tx.execute(
+                &format!("DROP TABLE IF EXISTS {}", collection.table_for(suffix)),
+                [],
+            )?;
+        }
+        tx.execute(
+            "DELETE FROM collections WHERE name = ?1",
+            params![collection.as_str()],
+        )?;
+        tx.execute(
+            "DELETE FROM sync_state WHERE collection = ?1",
+            params![collection.as_str()],
+        )?;
+        tx.execute(
+            "DELETE FROM chunk_settings WHERE collection = ?1",
+            params![collection.as_str()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
 }
 
 /// Vectorizer for text embedding using sqlite-vec
 #[derive(Clone)]
 pub struct Vectorizer {
     vector_db: Arc<Mutex<SqliteVector>>,
-    collection: String,
-    dimension: usize,
-    model: Arc<TextEmbedding>,
+    collection: CollectionName,
+    embedder: Arc<dyn Embedder>,
 }
 const CHUNK_SIZE: usize = 500;
 
 impl Vectorizer {
-    /// Creates a new Vectorizer with the specified database path
+    /// Creates a new Vectorizer with the specified database path, fails on an
+    /// invalid collection name
     pub fn new<P: AsRef<Path>>(
         db_path: P,
         collection: &str,
-        dimension: usize,
-        model: Arc<TextEmbedding>,
+        embedder: Arc<dyn Embedder>,
     ) -> Result<Self> {
+        let collection = CollectionName::new(collection)?;
         let vector_db = SqliteVector::new(db_path)
             .map_err(|e| anyhow!("Failed to create/open vector database: {}", e))?;
 
-        Ok(Self {
-            vector_db: Arc::new(Mutex::new(vector_db)),
-            collection: collection.to_string(),
-            dimension,
-            model: model,
-        })
+        Ok(Self {
+            vector_db: Arc::new(Mutex::new(vector_db)),
+            collection,
+            embedder,
+        })
+    }
+
+    /// Name of the collection this vectorizer reads and writes
+    pub fn collection(&self) -> &str {
+        self.collection.as_str()
+    }
+
+    /// Embedder used for documents and queries
+    pub fn embedder(&self) -> &dyn Embedder {
+        self.embedder.as_ref()
+    }
+
+    pub fn create_table(&self) -> Result<()> {
+        let params = VectorParams::new(self.embedder.dimension() as u32);
+
+        let v = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        self.check_model(&v)?;
+        v.create_vector_collection(&self.collection, params)
+            .map_err(|e| anyhow!("Failed to create vector collection: {}", e))?;
+        v.record_model(
+            &self.collection,
+            self.embedder.model(),
+            self.embedder.dimension(),
+        )?;
+        Ok(())
+    }
+
+    /// Refuses an embedder that differs from the one the collection was built with
+    fn check_model(&self, vd: &SqliteVector) -> Result<()> {
+        if let Some((model, dimension)) = vd.collection_model(&self.collection)?
+            && (model != self.embedder.model() || dimension != self.embedder.dimension())
+        {
+            bail!(
+                "collection `{}` was built with `{}` ({} dimensions) but is opened with `{}` ({} dimensions), re-ingest it with --full to switch models",
+                self.collection,
+                model,
+                dimension,
+                self.embedder.model(),
+                self.embedder.dimension()
+            );
+        }
+        Ok(())
+    }
+
+    /// Stores a text embedding in the database
+    pub fn store_embedding(&self, id: usize, text: &str, embedding: &[f32]) -> Result<()> {
+        // Store the embedding in the database
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+
+        vd.add_item(&self.collection, embedding)
+            .map_err(|e| anyhow!("Failed to add embedding: {}", e))?;
+
+        // Store the text as metadata
+        vd.add_mate(&self.collection, id, text)
+            .map_err(|e| anyhow!("Failed to set metadata: {}", e))?;
+
+        Ok(())
+    }
+
+    pub fn store_docs(&mut self, texts: Vec<&str>) -> Result<()> {
+        let total = texts.len();
+        for (index, chunk) in texts.chunks(CHUNK_SIZE).enumerate() {
+            let embeddings = self.embeds(chunk.to_vec())?;
+            let mut items = Vec::new();
+            let mut mates = Vec::new();
+            for ((id, text), embedding) in chunk.iter().enumerate().zip(embeddings.iter()) {
+                // id 需要全局唯一，这里加上 chunk 的偏移量
+                let global_id = id + (index * CHUNK_SIZE) + 1;
+                items.push((global_id, embedding.as_slice()));
+                mates.push((global_id, *text));
+            }
+            let mut vd = self
+                .vector_db
+                .lock()
+                .map_err(|_| anyhow!("Mutex poisoned"))?;
+            vd.add_items(&self.collection, items)?;
+            vd.add_mates(&self.collection, mates)?;
+            log::info!(
+                "Stored {}/{} chunks",
+                index * CHUNK_SIZE + chunk.len(),
+                total
+            );
+        }
+        Ok(())
+    }
+
+    /// Loads the per-file manifest of one docs version of the collection
+    pub fn manifest(&self, version: Option<&str>) -> Result<HashMap<String, ManifestEntry>> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.load_manifest(&self.collection, version)
+    }
+
+    /// Whether the collection was ingested with a manifest, for any version
+    pub fn has_manifest(&self) -> Result<bool> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.has_manifest(&self.collection)
+    }
+
+    /// Stores a search in `query_log` through the collection's connection
+    pub fn log_query(&self, entry: &QueryLogEntry) -> Result<i64> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.log_query(entry)
+    }
+
+    /// Commit the given docs version of the collection was last synced from
+    pub fn indexed_commit(&self, version: Option<&str>) -> Result<Option<String>> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.indexed_commit(&self.collection, version)
+    }
+
+    /// Records the commit the given docs version of the collection was synced from
+    pub fn set_indexed_commit(
+        &self,
+        version: Option<&str>,
+        url: &str,
+        commit_sha: &str,
+    ) -> Result<()> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.set_indexed_commit(&self.collection, version, url, commit_sha)
+    }
+
+    /// Splitter settings the given docs version of the collection was chunked with
+    pub fn chunk_settings(&self, version: Option<&str>) -> Result<Option<ChunkSettings>> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.chunk_settings(&self.collection, version)
+    }
+
+    /// Records the splitter settings the given docs version of the collection was chunked with
+    pub fn set_chunk_settings(
+        &self,
+        version: Option<&str>,
+        settings: &ChunkSettings,
+    ) -> Result<()> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.set_chunk_settings(&self.collection, version, settings)
     }
 
-    pub fn create_table(&self) -> Result<()> {
-        let params = VectorParams::new(self.dimension as u32);
+    /// Docs versions stored in the collection with their chunk counts
+    pub fn versions(&self) -> Result<Vec<(Option<String>, usize)>> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.versions(&self.collection)
+    }
 
-        let v = self
+    /// Embeds the chunks of one source file and replaces its previous rows
+    pub fn index_source(
+        &self,
+        version: Option<&str>,
+        source: &str,
+        content_hash: &str,
+        texts: Vec<&str>,
+    ) -> Result<Vec<i64>> {
+        let embeddings = if texts.is_empty() {
+            Vec::new()
+        } else {
+            self.embed_chunks(&texts)?
+        };
+        let items = embeddings
+            .iter()
+            .map(|e| e.as_slice())
+            .zip(texts)
+            .collect::<Vec<_>>();
+
+        let mut vd = self
             .vector_db
             .lock()
             .map_err(|_| anyhow!("Mutex poisoned"))?;
-        v.create_vector_collection(&self.collection, params)
-            .map_err(|e| anyhow!("Failed to create vector collection: {}", e))?;
-        Ok(())
+        vd.replace_source(&self.collection, version, source, content_hash, items)
     }
 
-    /// Stores a text embedding in the database
-    pub fn store_embedding(&self, id: usize, text: &str, embedding: &[f32]) -> Result<()> {
-        // Store the embedding in the database
+    /// Stores the original markdown of a source file, served as a `docs://` resource
+    pub fn store_page(
+        &self,
+        version: Option<&str>,
+        source: &str,
+        path: &str,
+        content: &str,
+    ) -> Result<()> {
         let vd = self
             .vector_db
             .lock()
             .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.store_page(&self.collection, version, source, path, content)
+    }
 
-        vd.add_item(&self.collection, embedding)
-            .map_err(|e| anyhow!("Failed to add embedding: {}", e))?;
+    /// Removes all rows of a source file, e.g. one that no longer exists
+    pub fn delete_by_source(&self, version: Option<&str>, source: &str) -> Result<usize> {
+        let mut vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.delete_by_source(&self.collection, version, source)
+    }
 
-        // Store the text as metadata
-        vd.add_mate(&self.collection, id, text)
-            .map_err(|e| anyhow!("Failed to set metadata: {}", e))?;
+    /// Removes rows by rowid, returns how many existed
+    pub fn delete_by_ids(&self, ids: &[i64]) -> Result<usize> {
+        let mut vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.delete_by_ids(&self.collection, ids)
+    }
 
-        Ok(())
+    /// Embeds a chunk line like `index_source` does and writes it at `id`,
+    /// replacing the row stored there
+    pub fn upsert_chunk(&self, id: i64, metadata: &str) -> Result<()> {
+        let embedding = self
+            .embed_chunks(&[metadata])?
+            .pop()
+            .ok_or_else(|| anyhow!("Failed to generate embedding for the chunk"))?;
+        let mut vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        self.check_model(&vd)?;
+        vd.upsert_chunk(&self.collection, id, &embedding, metadata)
     }
 
-    pub fn store_docs(&mut self, texts: Vec<&str>) -> Result<()> {
-        for (index, chunk) in texts.chunks(CHUNK_SIZE).enumerate() {
-            let embeddings = self.embeds(chunk.to_vec())?;
-            let mut items = Vec::new();
-            let mut mates = Vec::new();
-            for ((id, text), embedding) in chunk.iter().enumerate().zip(embeddings.iter()) {
-                // id 需要全局唯一，这里加上 chunk 的偏移量
-                let global_id = id + (index * CHUNK_SIZE) + 1;
-                items.push((global_id, embedding.as_slice()));
-                mates.push((global_id, *text));
-            }
-            let mut vd = self
-                .vector_db
-                .lock()
-                .map_err(|_| anyhow!("Mutex poisoned"))?;
-            vd.add_items(&self.collection, items)?;
-            vd.add_mates(&self.collection, mates)?;
-        }
-        Ok(())
+    /// Number of rows stored in the collection
+    pub fn count(&self) -> Result<usize> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.count(&self.collection)
     }
 
     pub fn embeds(&self, texts: Vec<&str>) -> Result<Vec<Vec<f32>>> {
-        self.model.embed(texts, None)
+        self.embedder.embed(texts)
+    }
+
+    /// Embeds chunk metadata lines by their [`StoredChunk::embedding_text`]
+    fn embed_chunks(&self, lines: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let texts = lines
+            .iter()
+            .map(|line| StoredChunk::parse(line).embedding_text())
+            .collect::<Vec<_>>();
+        self.embeds(texts.iter().map(|t| t.as_str()).collect())
     }
 
     /// Performs a similarity search
-    pub fn search(&self, text: &str, limit: Option<usize>) -> Result<Vec<(i64, Option<String>)>> {
+    pub fn search(
+        &self,
+        text: &str,
+        limit: Option<usize>,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchHit>> {
         // Search for similar embeddings
 
         let limit = match limit {
@@ -298,7 +2108,9 @@ impl Vectorizer {
             None => 20u32,
         };
         // dbg!(limit);
+        let started = Instant::now();
         let binding = self.embeds(vec![text])?;
+        metrics::global().observe_embedding(self.collection.as_str(), started.elapsed());
         let embedding = binding
             .first()
             .ok_or_else(|| anyhow!("Failed to generate embedding for the text"))?;
@@ -306,46 +2118,99 @@ impl Vectorizer {
             .vector_db
             .lock()
             .map_err(|_| anyhow!("Mutex poisoned"))?;
+        self.check_model(&vd)?;
 
+        let started = Instant::now();
         let results = vd
-            .search(&self.collection, embedding, limit)
+            .search(&self.collection, embedding, limit, filter)
             .map_err(|e| anyhow!("Failed to search: {}", e))?;
+        metrics::global().observe_query(self.collection.as_str(), "vector", started.elapsed());
 
         Ok(results)
     }
 
-    pub fn clean(&self) -> Result<()> {
-        let main_table = &self.collection;
-        let meta_table = format!("{}_metadata", main_table);
-        let sql_main = format!("DROP TABLE IF EXISTS {}", main_table);
-        let sql_meta = format!("DROP TABLE IF EXISTS {}", meta_table);
+    /// Performs a BM25 keyword search, building the index on first use for older collections
+    pub fn keyword_search(
+        &self,
+        text: &str,
+        limit: Option<usize>,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchHit>> {
+        let limit = limit.unwrap_or(20) as u32;
         let vd = self
             .vector_db
             .lock()
             .map_err(|_| anyhow!("Mutex poisoned"))?;
-
-        vd.conn.execute(sql_main.as_str(), [])?;
-        vd.conn.execute(sql_meta.as_str(), [])?;
-        Ok(())
+        vd.create_fts(&self.collection)?;
+        let started = Instant::now();
+        let results = vd
+            .keyword_search(&self.collection, text, limit, filter)
+            .map_err(|e| anyhow!("Failed to search: {}", e))?;
+        metrics::global().observe_query(self.collection.as_str(), "keyword", started.elapsed());
+        Ok(results)
     }
 
-    pub fn mock_embed(&self, text: &str) -> Vec<f32> {
-        let mut embedding = vec![0.0; self.dimension];
+    /// Fuses the vector and keyword rankings with reciprocal rank fusion
+    pub fn hybrid_search(
+        &self,
+        text: &str,
+        limit: Option<usize>,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchHit>> {
+        let limit = limit.unwrap_or(20);
+        let candidates = Some(limit.max(HYBRID_CANDIDATES));
+        let vector = self.search(text, candidates, filter)?;
+        let keyword = self.keyword_search(text, candidates, filter)?;
+        Ok(reciprocal_rank_fusion(vec![vector, keyword], limit))
+    }
 
-        for (i, c) in text.chars().enumerate() {
-            let pos = i % self.dimension;
-            embedding[pos] += (c as u32 % 256) as f32 / 128.0 - 1.0;
+    /// Searches the collection with the given ranking
+    pub fn search_with_mode(
+        &self,
+        text: &str,
+        limit: Option<usize>,
+        mode: SearchMode,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchHit>> {
+        match mode {
+            SearchMode::Vector => self.search(text, limit, filter),
+            SearchMode::Keyword => self.keyword_search(text, limit, filter),
+            SearchMode::Hybrid => self.hybrid_search(text, limit, filter),
         }
+    }
 
-        // Normalize the embedding
-        let magnitude: f32 = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
-        if magnitude > 0.0 {
-            for value in &mut embedding {
-                *value /= magnitude;
-            }
-        }
+    /// Over-fetches `candidates` hits with the given ranking and keeps the
+    /// `limit` best ones according to the reranker
+    pub fn search_reranked(
+        &self,
+        text: &str,
+        limit: usize,
+        mode: SearchMode,
+        filter: &SearchFilter,
+        reranker: &dyn Reranker,
+        candidates: usize,
+    ) -> Result<Vec<SearchHit>> {
+        let hits = self.search_with_mode(text, Some(candidates.max(limit)), mode, filter)?;
+        reranker::rerank(reranker, text, hits, limit)
+    }
+
+    /// Removes one docs version of the collection, dropping its tables when
+    /// no other version is left
+    pub fn clean_version(&self, version: Option<&str>) -> Result<()> {
+        let mut vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.drop_version(&self.collection, version)
+    }
 
-        embedding
+    /// Drops the collection with all its tables
+    pub fn clean(&self) -> Result<()> {
+        let vd = self
+            .vector_db
+            .lock()
+            .map_err(|_| anyhow!("Mutex poisoned"))?;
+        vd.drop_collection(&self.collection)
     }
 }
 
@@ -354,43 +2219,691 @@ mod tests {
     use std::fs::File;
     use std::io::{self, BufRead};
 
-    use fastembed::{EmbeddingModel, InitOptions};
-
     use super::*;
+    use crate::embedder::{FastEmbedder, MockEmbedder};
+
+    fn temp_db(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}.db3", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
     #[test]
-    fn test_search_docs() {
-        let documents = vec![
-            "passage: Hello, World!",
-            "query: Hello, World!",
-            "passage: This is an example passage.",
-            "fastembed-rs is licensed under Apache  2.0",
-        ];
+    fn test_replace_source() {
+        let path = temp_db("test_replace_source");
+        let mut db = SqliteVector::new(&path).unwrap();
+        let docs = CollectionName::new("docs").unwrap();
+        db.create_vector_collection(&docs, VectorParams::new(4))
+            .unwrap();
+
+        let a = [1.0, 0.0, 0.0, 0.0];
+        let b = [0.0, 1.0, 0.0, 0.0];
+        let first = db
+            .replace_source(&docs, None, "a.md", "h1", vec![(&a, "a0"), (&b, "a1")])
+            .unwrap();
+        let other = db
+            .replace_source(&docs, None, "b.md", "h2", vec![(&b, "b0")])
+            .unwrap();
+        assert_eq!(first, vec![1, 2]);
+        assert_eq!(other, vec![3]);
+
+        // 重新索引同一个文件只替换它自己的行
+        let second = db
+            .replace_source(&docs, None, "a.md", "h3", vec![(&a, "a0'")])
+            .unwrap();
+        assert_eq!(second, vec![4]);
+        assert_eq!(db.count(&docs).unwrap(), 2);
+
+        let hits = db.search(&docs, &a, 5, &SearchFilter::default()).unwrap();
+        assert_eq!(hits[0].rowid, 4);
+        assert_eq!(hits[0].chunk.as_ref().unwrap().text, "a0'");
+        assert!(hits[0].distance.unwrap().abs() < 1e-6);
+        assert!((hits[0].score - 1.0).abs() < 1e-6);
+
+        let manifest = db.load_manifest(&docs, None).unwrap();
+        assert_eq!(manifest["a.md"].content_hash, "h3");
+        assert_eq!(manifest["a.md"].rowids, vec![4]);
+
+        assert_eq!(db.delete_by_source(&docs, None, "b.md").unwrap(), 1);
+        assert_eq!(db.count(&docs).unwrap(), 1);
+        assert!(!db.load_manifest(&docs, None).unwrap().contains_key("b.md"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_index_source_embeds_chunk_text() {
+        let path = temp_db("test_index_source_embeds_chunk_text");
+        let vector = Vectorizer::new(&path, "docs", Arc::new(MockEmbedder::new(4))).unwrap();
+        vector.create_table().unwrap();
+        vector
+            .index_source(
+                None,
+                "/docs/queues.md",
+                "h1",
+                vec![
+                    r#"{"id":"q-0","text":"Job batching","source":"/docs/queues.md","section":"Queues > Job Batching"}"#,
+                ],
+            )
+            .unwrap();
+        // 向量只包含标题路径和正文, 不包含 json 的键和 id
+        let hits = vector
+            .search(
+                "Queues > Job Batching\n\nJob batching",
+                Some(1),
+                &SearchFilter::default(),
+            )
+            .unwrap();
+        assert!(hits[0].distance.unwrap().abs() < 1e-6);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_upsert_chunk_embeds_like_index_source() {
+        let path = temp_db("test_upsert_chunk_embeds_like_index_source");
+        let vector = Vectorizer::new(&path, "docs", Arc::new(MockEmbedder::new(4))).unwrap();
+        vector.create_table().unwrap();
+        let line = r#"{"id":"q-0","text":"Job batching","source":"/docs/queues.md","section":"Queues > Job Batching"}"#;
+        let rowids = vector
+            .index_source(None, "/docs/queues.md", "h1", vec![line])
+            .unwrap();
+        vector.upsert_chunk(10, line).unwrap();
+
+        // 同一个块无论从哪条路径写入, 向量都相同
+        let hits = vector
+            .search(
+                "Queues > Job Batching\n\nJob batching",
+                Some(2),
+                &SearchFilter::default(),
+            )
+            .unwrap();
+        let mut found = hits.iter().map(|hit| hit.rowid).collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, vec![rowids[0], 10]);
+        assert!(hits.iter().all(|hit| hit.distance.unwrap().abs() < 1e-6));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_delete_and_upsert() {
+        let path = temp_db("test_delete_and_upsert");
+        let vector = Vectorizer::new(&path, "docs", Arc::new(MockEmbedder::new(4))).unwrap();
+        vector.create_table().unwrap();
+        let rowids = vector
+            .index_source(
+                None,
+                "queues.md",
+                "h1",
+                vec![
+                    r#"{"id":"q-0","text":"Dispatching jobs","source":"queues.md"}"#,
+                    r#"{"id":"q-1","text":"Job batching","source":"queues.md"}"#,
+                ],
+            )
+            .unwrap();
+        // store_docs 写入的行没有 manifest 记录, 只能按 metadata 的 source 找到
+        vector
+            .upsert_chunk(
+                10,
+                r#"{"id":"m-0","text":"Mocking facades","source":"mocking.md"}"#,
+            )
+            .unwrap();
+        assert_eq!(vector.count().unwrap(), 3);
+
+        vector
+            .upsert_chunk(
+                rowids[0],
+                r#"{"id":"q-0","text":"Queued closures","source":"queues.md"}"#,
+            )
+            .unwrap();
+        assert_eq!(vector.count().unwrap(), 3);
+        let filter = SearchFilter::default();
+        let hits = vector.keyword_search("closures", Some(5), &filter).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].rowid, rowids[0]);
+        assert!(
+            vector
+                .keyword_search("Dispatching", Some(5), &filter)
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(vector.delete_by_ids(&[rowids[1], 99]).unwrap(), 1);
+        assert_eq!(
+            vector.manifest(None).unwrap()["queues.md"].rowids,
+            vec![rowids[0]]
+        );
+        assert!(
+            vector
+                .keyword_search("batching", Some(5), &filter)
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(vector.delete_by_source(None, "mocking.md").unwrap(), 1);
+        assert_eq!(vector.delete_by_source(None, "queues.md").unwrap(), 1);
+        assert_eq!(vector.count().unwrap(), 0);
+        assert!(vector.manifest(None).unwrap().is_empty());
+        assert!(
+            vector
+                .search("Queued closures", Some(5), &filter)
+                .unwrap()
+                .is_empty()
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_collection_catalog() {
+        let path = temp_db("test_collection_catalog");
+        // 目录表出现之前建的集合在迁移时补登记
+        SqliteVector::open(&path)
+            .unwrap()
+            .conn
+            .execute(
+                "CREATE VIRTUAL TABLE legacy USING vec0(embedding FLOAT[2])",
+                [],
+            )
+            .unwrap();
+        let db = SqliteVector::new(&path).unwrap();
+        let legacy = CollectionName::new("legacy").unwrap();
+        let docs = CollectionName::new("docs").unwrap();
+        assert_eq!(db.collections().unwrap(), vec![legacy.clone()]);
+        assert!(db.count(&docs).is_err());
+
+        db.create_vector_collection(&docs, VectorParams::new(2))
+            .unwrap();
+        db.set_metadata(&docs).unwrap();
+        assert_eq!(db.count(&docs).unwrap(), 0);
+        assert_eq!(db.collections().unwrap(), vec![docs.clone(), legacy]);
+        assert!(db.has_collection(&docs).unwrap());
+
+        db.drop_collection(&docs).unwrap();
+        assert!(db.count(&docs).is_err());
+        assert!(!db.has_collection(&docs).unwrap());
+        assert_eq!(db.collections().unwrap().len(), 1);
+
+        // 同名的普通表不会被当成集合登记
+        db.conn
+            .execute("CREATE TABLE notes (body TEXT)", [])
+            .unwrap();
+        let notes = CollectionName::new("notes").unwrap();
+        assert!(
+            db.create_vector_collection(&notes, VectorParams::new(2))
+                .is_err()
+        );
+        assert_eq!(db.collections().unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_search_filters() {
+        let path = temp_db("test_search_filters");
+        let mut db = SqliteVector::new(&path).unwrap();
+        let docs = CollectionName::new("docs").unwrap();
+        db.create_vector_collection(&docs, VectorParams::new(2))
+            .unwrap();
+        for (i, (page, section, embedding)) in [
+            ("eloquent.md", "Eloquent > Faking Models", [1.0, 0.0]),
+            ("queues.md", "Queues > Faking Queued Jobs", [0.8, 0.6]),
+            ("mocking.md", "Mocking > Queue Fake", [0.6, 0.8]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let source = format!("/repo/docs/{}", page);
+            // mocking.md 模拟页面表出现之前导入的文件
+            if page != "mocking.md" {
+                db.store_page(&docs, None, &source, page, "").unwrap();
+            }
+            let metadata = serde_json::json!({
+                "id": format!("{}-0", i),
+                "text": format!("fake {}", page),
+                "source": source,
+                "section": section,
+            })
+            .to_string();
+            db.replace_source(&docs, None, &source, "h", vec![(&embedding, &metadata)])
+                .unwrap();
+        }
+
+        let sources = |hits: Vec<SearchHit>| {
+            hits.into_iter()
+                .map(|hit| hit.chunk.unwrap().source.unwrap())
+                .collect::<Vec<_>>()
+        };
+        // eloquent.md 最近, 过滤在 KNN 之前生效, 取 1 条仍然命中过滤后的页面
+        let filter = SearchFilter::default()
+            .with_source_prefixes(vec!["queue".to_string(), "mock".to_string()]);
+        assert_eq!(
+            sources(db.search(&docs, &[1.0, 0.0], 1, &filter).unwrap()),
+            vec!["/repo/docs/queues.md"]
+        );
+        assert_eq!(
+            sources(db.keyword_search(&docs, "fake", 5, &filter).unwrap()).len(),
+            2
+        );
+
+        let filter = SearchFilter::default().with_section(Some("queue fake".to_string()));
+        assert_eq!(
+            sources(db.search(&docs, &[1.0, 0.0], 3, &filter).unwrap()),
+            vec!["/repo/docs/mocking.md"]
+        );
+        // LIKE 通配符按字面匹配
+        let filter = SearchFilter::default().with_source_prefixes(vec!["%".to_string()]);
+        assert!(
+            db.search(&docs, &[1.0, 0.0], 3, &filter)
+                .unwrap()
+                .is_empty()
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_metadata_migration() {
+        let path = temp_db("test_metadata_migration");
+        {
+            // 没有 schema_migrations 之前的数据库: metadata 列存 jsonl 行或纯文本
+            let db = SqliteVector::open(&path).unwrap();
+            db.conn
+                .execute_batch(
+                    r#"CREATE TABLE collections (name TEXT PRIMARY KEY, model TEXT NOT NULL, dimension INTEGER NOT NULL, created_at TEXT NOT NULL);
+                    CREATE VIRTUAL TABLE legacy USING vec0(embedding FLOAT[2]);
+                    CREATE TABLE legacy_metadata (id INTEGER PRIMARY KEY, metadata BLOB);
+                    INSERT INTO legacy_metadata VALUES
+                        (1, '{"id":"l-3","text":"Queue fakes","source":"queues.md","section":"Queues > Testing","version":"11.x"}'),
+                        (2, 'plain text row');"#,
+                )
+                .unwrap();
+            assert_eq!(db.schema_version().unwrap(), 0);
+        }
+        let db = SqliteVector::new(&path).unwrap();
+        let legacy = CollectionName::new("legacy").unwrap();
+        assert_eq!(db.schema_version().unwrap(), migrations::latest_version());
+        assert!(db.pending_migrations().unwrap().is_empty());
+        assert_eq!(db.collections().unwrap(), vec![legacy.clone()]);
+
+        let chunk = db
+            .chunks_by_ids(&legacy, &["l-3".to_string()])
+            .unwrap()
+            .remove(0);
+        assert_eq!(chunk.section.as_deref(), Some("Queues > Testing"));
+        assert_eq!(chunk.position, Some(3));
+        assert_eq!(chunk.content_hash, TextChunker::content_hash("Queue fakes"));
+        assert_eq!(
+            db.versions(&legacy).unwrap(),
+            vec![(None, 1), (Some("11.x".to_string()), 1)]
+        );
+        db.create_fts(&legacy).unwrap();
+        let hits = db
+            .keyword_search(&legacy, "plain", 5, &SearchFilter::default())
+            .unwrap();
+        assert_eq!(hits[0].chunk.as_ref().unwrap().text, "plain text row");
+        assert_eq!(hits[0].chunk.as_ref().unwrap().chunk_id, None);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_keyword_and_hybrid_search() {
+        let path = temp_db("test_keyword_search");
+        let mut db = SqliteVector::new(&path).unwrap();
+        let docs = CollectionName::new("docs").unwrap();
+        db.create_vector_collection(&docs, VectorParams::new(2))
+            .unwrap();
+
+        let near = [1.0, 0.0];
+        let far = [0.0, 1.0];
+        db.replace_source(
+            &docs,
+            None,
+            "a.md",
+            "h1",
+            vec![
+                (&near, r#"{"id":"a-0","text":"Query relationships of a model","source":"a.md"}"#),
+                (&far, r#"{"id":"a-1","text":"Use whereBelongsTo to query the parent","source":"a.md","section":"Eloquent > whereBelongsTo"}"#),
+            ],
+        )
+        .unwrap();
+        db.replace_source(
+            &docs,
+            None,
+            "b.md",
+            "h2",
+            vec![(&far, "Run php artisan schedule:work locally")],
+        )
+        .unwrap();
+
+        let hits = db
+            .keyword_search(&docs, "wherebelongsto", 5, &SearchFilter::default())
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].rowid, 2);
+        assert_eq!(hits[0].distance, None);
+
+        let hits = db
+            .keyword_search(
+                &docs,
+                "php artisan schedule:work",
+                5,
+                &SearchFilter::default(),
+            )
+            .unwrap();
+        assert_eq!(hits[0].rowid, 3);
+
+        // 关键词排名第一的块在融合后超过向量排名第一的块
+        let vector = db
+            .search(&docs, &near, 3, &SearchFilter::default())
+            .unwrap();
+        let keyword = db
+            .keyword_search(&docs, "whereBelongsTo", 3, &SearchFilter::default())
+            .unwrap();
+        assert_eq!(vector[0].rowid, 1);
+        let fused = reciprocal_rank_fusion(vec![vector, keyword], 2);
+        assert_eq!(fused.len(), 2);
+        assert_eq!(fused[0].rowid, 2);
+        assert_eq!(fused[1].rowid, 1);
+        assert!(fused[0].distance.is_some());
+
+        // 删除文件时关键词索引一起清理
+        db.delete_by_source(&docs, None, "a.md").unwrap();
+        assert!(
+            db.keyword_search(&docs, "whereBelongsTo", 5, &SearchFilter::default())
+                .unwrap()
+                .is_empty()
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_versioned_sources() {
+        let path = temp_db("test_versioned_sources");
+        let mut db = SqliteVector::new(&path).unwrap();
+        let docs = CollectionName::new("docs").unwrap();
+        db.create_vector_collection(&docs, VectorParams::new(2))
+            .unwrap();
+
+        let e = [1.0, 0.0];
+        db.replace_source(
+            &docs,
+            Some("10.x"),
+            "eloquent.md",
+            "h10",
+            vec![(
+                &e,
+                r#"{"id":"a","text":"Eloquent on 10","source":"eloquent.md","version":"10.x"}"#,
+            )],
+        )
+        .unwrap();
+        db.replace_source(
+            &docs,
+            Some("12.x"),
+            "eloquent.md",
+            "h12",
+            vec![(
+                &e,
+                r#"{"id":"b","text":"Eloquent on 12","source":"eloquent.md","version":"12.x"}"#,
+            )],
+        )
+        .unwrap();
+
+        // 同一个文件的不同版本互不覆盖
+        assert_eq!(db.count(&docs).unwrap(), 2);
+        assert_eq!(
+            db.load_manifest(&docs, Some("10.x")).unwrap()["eloquent.md"].rowids,
+            vec![1]
+        );
+        assert!(db.load_manifest(&docs, None).unwrap().is_empty());
+        assert_eq!(
+            db.versions(&docs).unwrap(),
+            vec![(Some("10.x".to_string()), 1), (Some("12.x".to_string()), 1)]
+        );
+
+        let filter = SearchFilter::default().with_version(Some("10.x".to_string()));
+        let hits = db.search(&docs, &e, 5, &filter).unwrap();
+        assert_eq!(hits.iter().map(|h| h.rowid).collect::<Vec<_>>(), vec![1]);
+        let hits = db.keyword_search(&docs, "eloquent", 5, &filter).unwrap();
+        assert_eq!(hits.iter().map(|h| h.rowid).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(
+            db.search(&docs, &e, 5, &SearchFilter::default())
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_query_log() {
+        let path = temp_db("test_query_log");
+        let db = SqliteVector::new(&path).unwrap();
+        let entry = QueryLogEntry {
+            collection: "laravel_docs".to_string(),
+            query: "eager loading".to_string(),
+            mode: "hybrid".to_string(),
+            version: Some("11.x".to_string()),
+            client: None,
+            hits: vec![
+                LoggedHit {
+                    id: "a-0".to_string(),
+                    score: 0.9,
+                },
+                LoggedHit {
+                    id: "a-1".to_string(),
+                    score: 0.5,
+                },
+            ],
+            latency_ms: 12.5,
+        };
+        let id = db.log_query(&entry).unwrap();
+        assert_eq!(db.logged_query(id).unwrap(), Some(entry));
+        assert_eq!(db.logged_query(id + 1).unwrap(), None);
+
+        let helpful = vec!["a-1".to_string()];
+        assert_eq!(db.record_feedback(id, &helpful).unwrap(), 1);
+        // 重复反馈不会重复计数
+        assert_eq!(db.record_feedback(id, &helpful).unwrap(), 0);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_vectorizer_query_log() {
+        let path = temp_db("test_vectorizer_query_log");
+        let vector = Vectorizer::new(&path, "docs", Arc::new(MockEmbedder::new(4))).unwrap();
+        vector.create_table().unwrap();
+        let entry = QueryLogEntry {
+            collection: "docs".to_string(),
+            query: "job batching".to_string(),
+            mode: "vector".to_string(),
+            version: None,
+            client: Some("ci".to_string()),
+            hits: vec![LoggedHit {
+                id: "q-0".to_string(),
+                score: 0.8,
+            }],
+            latency_ms: 3.0,
+        };
+        // 通过集合的连接写入, 其它连接也能读到
+        let id = vector.log_query(&entry).unwrap();
+        let db = SqliteVector::new(&path).unwrap();
+        assert_eq!(db.logged_query(id).unwrap(), Some(entry));
+        assert_eq!(db.record_feedback(id, &["q-0".to_string()]).unwrap(), 1);
+        let feedback: i64 = db
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM query_feedback WHERE query_id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(feedback, 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_pages() {
+        let path = temp_db("test_pages");
+        let mut db = SqliteVector::new(&path).unwrap();
+        let docs = CollectionName::new("docs").unwrap();
+        db.create_vector_collection(&docs, VectorParams::new(2))
+            .unwrap();
+
+        let e = [1.0, 0.0];
+        db.replace_source(
+            &docs,
+            Some("12.x"),
+            "/repo/docs/routing.md",
+            "h1",
+            vec![
+                (
+                    &e,
+                    r##"{"id":"a-0","text":"# Routing","source":"/repo/docs/routing.md"}"##,
+                ),
+                (
+                    &e,
+                    r#"{"id":"a-1","text":"Basic routes","source":"/repo/docs/routing.md"}"#,
+                ),
+            ],
+        )
+        .unwrap();
+        db.replace_source(&docs, None, "/repo/docs/old.md", "h2", vec![(&e, "legacy")])
+            .unwrap();
+        db.store_page(
+            &docs,
+            Some("12.x"),
+            "/repo/docs/routing.md",
+            "routing.md",
+            "# Routing\n\nBasic routes\n",
+        )
+        .unwrap();
+
+        let pages = db.pages(&docs).unwrap();
+        assert_eq!(
+            pages
+                .iter()
+                .map(|p| (p.version.as_deref(), p.path.as_str(), p.size))
+                .collect::<Vec<_>>(),
+            vec![
+                (None, "old.md", None),
+                (Some("12.x"), "routing.md", Some(24))
+            ]
+        );
+        assert_eq!(
+            db.page_content(&docs, Some("12.x"), "/repo/docs/routing.md")
+                .unwrap()
+                .as_deref(),
+            Some("# Routing\n\nBasic routes\n")
+        );
+        // 没有存原文的页面由 chunk 拼回
+        assert_eq!(
+            db.page_content(&docs, None, "/repo/docs/old.md")
+                .unwrap()
+                .as_deref(),
+            Some("legacy")
+        );
+        assert!(
+            db.page_content(&docs, None, "/repo/docs/routing.md")
+                .unwrap()
+                .is_none()
+        );
+
+        let ids = ["a-1", "missing", "a-0"].map(|id| id.to_string());
+        let chunks = db.chunks_by_ids(&docs, &ids).unwrap();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.chunk_id.as_deref(), c.position, c.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some("a-1"), Some(1), "Basic routes"),
+                (Some("a-0"), Some(0), "# Routing"),
+            ]
+        );
+        assert_eq!(chunks[0].source.as_deref(), Some("/repo/docs/routing.md"));
+        assert_eq!(chunks[0].token_count, 2);
+
+        db.delete_by_source(&docs, Some("12.x"), "/repo/docs/routing.md")
+            .unwrap();
+        assert_eq!(db.pages(&docs).unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_refuses_mismatched_embedder() {
+        let path = temp_db("test_mismatched_embedder");
+        let vector = Vectorizer::new(&path, "docs", Arc::new(MockEmbedder::new(4))).unwrap();
+        vector.create_table().unwrap();
+        vector
+            .index_source(None, "a.md", "h1", vec!["whereBelongsTo", "HasUuids"])
+            .unwrap();
+        assert_eq!(
+            vector
+                .search("HasUuids", Some(1), &SearchFilter::default())
+                .unwrap()[0]
+                .rowid,
+            2
+        );
 
+        let db = SqliteVector::new(&path).unwrap();
+        let docs = CollectionName::new("docs").unwrap();
+        assert_eq!(
+            db.collection_model(&docs).unwrap(),
+            Some(("mock".to_string(), 4))
+        );
+
+        let other = Vectorizer::new(&path, "docs", Arc::new(MockEmbedder::new(8))).unwrap();
+        assert!(
+            other
+                .search("HasUuids", Some(1), &SearchFilter::default())
+                .is_err()
+        );
+        assert!(other.create_table().is_err());
+
+        // 全量重建后可以切换模型
+        other.clean().unwrap();
+        other.create_table().unwrap();
+        assert_eq!(
+            db.collection_model(&docs).unwrap(),
+            Some(("mock".to_string(), 8))
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_search_docs() {
         let file = File::open("/Users/fyyx/Documents/rust_projects/rust-mcp-demo/artifacts/chunks/laravel-comments-documentation_chunks_SZ_400_O_20.jsonl").unwrap();
         let reader = io::BufReader::new(file);
         let documents: Vec<String> = reader.lines().collect::<Result<_, _>>().unwrap();
         let documents = documents.iter().map(|i| i.as_str()).collect::<Vec<&str>>();
-        let model = Arc::new(
-            TextEmbedding::try_new(
-                InitOptions::new(EmbeddingModel::AllMiniLML6V2)
-                    .with_cache_dir("~/.fastembed_cache".into())
-                    .with_show_download_progress(true),
-            )
-            .unwrap(),
-        );
-        let mut vector = Vectorizer::new("./aa.db3", "laravel_comments_docs", 384, model).unwrap();
+        let model =
+            Arc::new(FastEmbedder::new("AllMiniLML6V2", Path::new("~/.fastembed_cache")).unwrap());
+        let mut vector = Vectorizer::new("./aa.db3", "laravel_comments_docs", model).unwrap();
         vector.clean().unwrap();
         vector.create_table().unwrap();
         vector.store_docs(documents.clone()).unwrap();
-        let result: Vec<(i64, Option<String>)> =
-            vector.search(documents.first().unwrap(), None).unwrap();
+        let result: Vec<SearchHit> = vector
+            .search(documents.first().unwrap(), None, &SearchFilter::default())
+            .unwrap();
 
         assert_eq!(
             result
-                .iter()
-                .map(|(_, doc)| doc.as_deref().unwrap_or(""))
-                .collect::<Vec<&str>>(),
+                .into_iter()
+                .map(|hit| hit.chunk.unwrap_or_default())
+                .collect::<Vec<_>>(),
             documents
+                .iter()
+                .map(|d| StoredChunk::parse(d))
+                .collect::<Vec<_>>()
         );
     }
 }